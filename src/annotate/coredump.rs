@@ -0,0 +1,422 @@
+//! ELF core dump annotation.
+//!
+//! Labels the ELF header, the program header table and every note, and maps
+//! each loadable segment to its virtual address. The `NT_FILE` note of Linux
+//! cores names the file each segment was mapped from.
+
+use std::io;
+
+use super::{Annotations, Mapping, Reader, not_format, truncated};
+
+/// Program header type of loadable segments.
+const PT_LOAD: u32 = 1;
+
+/// Program header type of note segments.
+const PT_NOTE: u32 = 4;
+
+/// Note types of the `CORE` owner and their names.
+const CORE_NOTES: [(u32, &str); 7] = [
+    (1, "NT_PRSTATUS"),
+    (2, "NT_PRFPREG"),
+    (3, "NT_PRPSINFO"),
+    (4, "NT_TASKSTRUCT"),
+    (6, "NT_AUXV"),
+    (0x5349_4749, "NT_SIGINFO"),
+    (0x4649_4C45, "NT_FILE"),
+];
+
+/// Note type of the `NT_FILE` mapped files list.
+const NT_FILE: u32 = 0x4649_4C45;
+
+/// A parsed program header.
+struct Phdr {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+}
+
+/// Annotates the ELF file behind `r`.
+pub fn annotate(r: &mut Reader) -> io::Result<Annotations> {
+    let mut a = Annotations::default();
+
+    if r.len < 52 || r.bytes(0, 4)? != b"\x7fELF" {
+        return Err(not_format("an ELF file"));
+    }
+
+    let wide = match r.u8(4)? {
+        1 => false,
+        2 => true,
+        _ => return Err(not_format("a 32 or 64 bit ELF file")),
+    };
+    r.big = match r.u8(5)? {
+        1 => false,
+        2 => true,
+        _ => return Err(not_format("a little or big endian ELF file")),
+    };
+
+    let kind = r.u16(16)?;
+    let machine = r.u16(18)?;
+    let (phoff, shoff, phentsize, phnum, shnum) = if wide {
+        (r.u64(32)?, r.u64(40)?, r.u16(54)?, r.u16(56)?, r.u16(60)?)
+    } else {
+        (
+            r.u32(28)? as u64,
+            r.u32(32)? as u64,
+            r.u16(42)?,
+            r.u16(44)?,
+            r.u16(48)?,
+        )
+    };
+
+    a.label(
+        0,
+        format!(
+            "ELF header: {} bit {} endian, type {}, machine {}",
+            if wide { 64 } else { 32 },
+            if r.big { "big" } else { "little" },
+            match kind {
+                1 => "REL".to_owned(),
+                2 => "EXEC".to_owned(),
+                3 => "DYN".to_owned(),
+                4 => "CORE".to_owned(),
+                k => format!("0x{:x}", k),
+            },
+            machine_name(machine)
+        ),
+    );
+
+    if phnum > 0 {
+        a.label(
+            phoff,
+            format!(
+                "program header table: {} entries of {} bytes",
+                phnum, phentsize
+            ),
+        );
+    }
+    if shoff != 0 && shnum > 0 {
+        a.label(shoff, format!("section header table: {} entries", shnum));
+    }
+
+    let mut phdrs = Vec::with_capacity(phnum as usize);
+    for i in 0..phnum as u64 {
+        let at = phoff
+            .checked_add(i * phentsize as u64)
+            .ok_or_else(|| truncated(phoff))?;
+
+        let p = if wide {
+            Phdr {
+                kind: r.u32(at)?,
+                flags: r.u32(at + 4)?,
+                offset: r.u64(at + 8)?,
+                vaddr: r.u64(at + 16)?,
+                filesz: r.u64(at + 32)?,
+                memsz: r.u64(at + 40)?,
+            }
+        } else {
+            Phdr {
+                kind: r.u32(at)?,
+                offset: r.u32(at + 4)? as u64,
+                vaddr: r.u32(at + 8)? as u64,
+                filesz: r.u32(at + 16)? as u64,
+                memsz: r.u32(at + 20)? as u64,
+                flags: r.u32(at + 24)?,
+            }
+        };
+
+        a.label(
+            at,
+            format!(
+                "program header {}: {} {} offset 0x{:x} vaddr 0x{:x} filesz 0x{:x} memsz 0x{:x}",
+                i,
+                segment_name(p.kind),
+                flags(p.flags),
+                p.offset,
+                p.vaddr,
+                p.filesz,
+                p.memsz
+            ),
+        );
+        phdrs.push(p);
+    }
+
+    // Mapped files from NT_FILE as (start, end, path)
+    let mut files = Vec::new();
+
+    for p in phdrs.iter().filter(|p| p.kind == PT_NOTE) {
+        let end = p.offset.saturating_add(p.filesz);
+        let mut at = p.offset;
+
+        while at.checked_add(12).is_some_and(|header| header <= end) {
+            let namesz = r.u32(at)? as u64;
+            let descsz = r.u32(at + 4)? as u64;
+            let kind = r.u32(at + 8)?;
+
+            // Name and descriptor are both padded to 4 bytes
+            let owner = r.str(at + 12, namesz)?;
+            let desc = at + 12 + namesz.next_multiple_of(4);
+
+            let what = match owner.as_str() {
+                "CORE" => describe(r, kind, desc, descsz, wide, &mut files)?,
+                "LINUX" if kind == 0x202 => "NT_X86_XSTATE".to_owned(),
+                _ => format!("type 0x{:x}", kind),
+            };
+            a.label(at, format!("note {} {}, {} bytes", owner, what, descsz));
+
+            at = desc + descsz.next_multiple_of(4);
+        }
+    }
+
+    for (i, p) in phdrs.iter().enumerate() {
+        if p.kind != PT_LOAD || p.filesz == 0 {
+            continue;
+        }
+
+        let mut name = format!("LOAD {} {}", i, flags(p.flags));
+        if let Some((_, _, path)) = files
+            .iter()
+            .find(|&&(start, end, _)| (start..end).contains(&p.vaddr))
+        {
+            name = format!("{} {}", name, path);
+        }
+
+        a.label(
+            p.offset,
+            format!(
+                "segment {} vaddr 0x{:x}..0x{:x}",
+                name,
+                p.vaddr,
+                p.vaddr.saturating_add(p.filesz)
+            ),
+        );
+        a.mappings.push(Mapping {
+            offset: p.offset,
+            len: p.filesz,
+            vaddr: p.vaddr,
+            name,
+        });
+    }
+
+    Ok(a)
+}
+
+/// Describes a `CORE` note, collecting mapped files from `NT_FILE` into `files`.
+fn describe(
+    r: &Reader,
+    kind: u32,
+    desc: u64,
+    descsz: u64,
+    wide: bool,
+    files: &mut Vec<(u64, u64, String)>,
+) -> io::Result<String> {
+    let name = match CORE_NOTES.iter().find(|&&(k, _)| k == kind) {
+        Some(&(_, name)) => name,
+        None => return Ok(format!("type 0x{:x}", kind)),
+    };
+
+    // Layouts below are the Linux ones, where 32 and 64 bit differ
+    let (pid, fname) = if wide { (32, 40) } else { (24, 28) };
+
+    let details = match kind {
+        // prstatus, with the pid after the signal info
+        1 if descsz >= pid + 4 => {
+            let signal = r.u16(desc + 12)?;
+            let pid = r.u32(desc + pid)?;
+
+            format!(" pid {} signal {}", pid, signal_name(signal as u32))
+        }
+        // prpsinfo, ending with the 16 byte name and 80 byte arguments
+        3 if descsz >= fname + 96 => {
+            format!(
+                " {} ({})",
+                r.str(desc + fname, 16)?,
+                r.str(desc + fname + 16, 80)?.trim_end()
+            )
+        }
+        0x5349_4749 if descsz >= 4 => format!(" signal {}", signal_name(r.u32(desc)?)),
+        NT_FILE => {
+            let w = if wide { 8 } else { 4 };
+            let count = r.word(desc, wide)?;
+
+            // Entries are (start, end, page offset) words, names follow them
+            let names_at = (desc + 2 * w).saturating_add(count.saturating_mul(3 * w));
+            let names = r.bytes(names_at, (desc + descsz).saturating_sub(names_at))?;
+            let mut names = names.split(|&b| b == 0);
+
+            for i in 0..count {
+                let at = desc + 2 * w + i * 3 * w;
+                let (start, end) = (r.word(at, wide)?, r.word(at + w, wide)?);
+                let path = names.next().unwrap_or_default();
+
+                files.push((start, end, String::from_utf8_lossy(path).into_owned()));
+            }
+
+            format!(" {} mapped files", count)
+        }
+        _ => String::new(),
+    };
+
+    Ok(format!("{}{}", name, details))
+}
+
+/// Names a program header type.
+fn segment_name(kind: u32) -> String {
+    match kind {
+        0 => "NULL",
+        1 => "LOAD",
+        2 => "DYNAMIC",
+        3 => "INTERP",
+        4 => "NOTE",
+        6 => "PHDR",
+        7 => "TLS",
+        0x6474_E550 => "GNU_EH_FRAME",
+        0x6474_E551 => "GNU_STACK",
+        0x6474_E552 => "GNU_RELRO",
+        0x6474_E553 => "GNU_PROPERTY",
+        k => return format!("0x{:x}", k),
+    }
+    .to_owned()
+}
+
+/// Formats segment permission flags as `rwx`.
+fn flags(flags: u32) -> String {
+    [(4, 'r'), (2, 'w'), (1, 'x')]
+        .iter()
+        .map(|&(bit, c)| if flags & bit != 0 { c } else { '-' })
+        .collect()
+}
+
+/// Names a machine type.
+fn machine_name(machine: u16) -> String {
+    match machine {
+        3 => "x86".to_owned(),
+        8 => "MIPS".to_owned(),
+        20 => "PowerPC".to_owned(),
+        21 => "PowerPC64".to_owned(),
+        22 => "S390".to_owned(),
+        40 => "ARM".to_owned(),
+        62 => "x86-64".to_owned(),
+        183 => "AArch64".to_owned(),
+        243 => "RISC-V".to_owned(),
+        m => format!("{}", m),
+    }
+}
+
+/// Names the signals that commonly end in a core dump.
+fn signal_name(signal: u32) -> String {
+    match signal {
+        3 => "SIGQUIT".to_owned(),
+        4 => "SIGILL".to_owned(),
+        5 => "SIGTRAP".to_owned(),
+        6 => "SIGABRT".to_owned(),
+        7 => "SIGBUS".to_owned(),
+        8 => "SIGFPE".to_owned(),
+        11 => "SIGSEGV".to_owned(),
+        s => format!("{}", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::annotate::{
+        Kind,
+        tests::{annotate_bytes, label},
+    };
+
+    /// Builds a 32 bit little endian core holding a single note segment.
+    fn core32(kind: u32, desc: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 52];
+        elf[..7].copy_from_slice(b"\x7fELF\x01\x01\x01");
+        elf[16..18].copy_from_slice(&4u16.to_le_bytes());
+        elf[18..20].copy_from_slice(&3u16.to_le_bytes());
+        elf[28..32].copy_from_slice(&52u32.to_le_bytes());
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes());
+        elf[44..46].copy_from_slice(&1u16.to_le_bytes());
+
+        // PT_NOTE right after the header, the note right after it
+        let mut phdr = [0u8; 32];
+        phdr[..4].copy_from_slice(&4u32.to_le_bytes());
+        phdr[4..8].copy_from_slice(&84u32.to_le_bytes());
+        phdr[16..20].copy_from_slice(&(20 + desc.len() as u32).to_le_bytes());
+        elf.extend(phdr);
+
+        elf.extend(5u32.to_le_bytes());
+        elf.extend((desc.len() as u32).to_le_bytes());
+        elf.extend(kind.to_le_bytes());
+        elf.extend(b"CORE\0\0\0\0");
+        elf.extend(desc);
+
+        elf
+    }
+
+    /// Builds a 64 bit little endian core from `(type, offset, vaddr, filesz)` program headers.
+    fn core64(phoff: u64, phdrs: &[(u32, u64, u64, u64)]) -> Vec<u8> {
+        let mut elf = vec![0u8; 64];
+        elf[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+        elf[16..18].copy_from_slice(&4u16.to_le_bytes());
+        elf[18..20].copy_from_slice(&62u16.to_le_bytes());
+        elf[32..40].copy_from_slice(&phoff.to_le_bytes());
+        elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+        elf[56..58].copy_from_slice(&(phdrs.len() as u16).to_le_bytes());
+
+        for &(kind, offset, vaddr, filesz) in phdrs {
+            let mut phdr = [0u8; 56];
+            phdr[..4].copy_from_slice(&kind.to_le_bytes());
+            phdr[4..8].copy_from_slice(&4u32.to_le_bytes());
+            phdr[8..16].copy_from_slice(&offset.to_le_bytes());
+            phdr[16..24].copy_from_slice(&vaddr.to_le_bytes());
+            phdr[32..40].copy_from_slice(&filesz.to_le_bytes());
+            phdr[40..48].copy_from_slice(&filesz.to_le_bytes());
+            elf.extend(phdr);
+        }
+
+        elf
+    }
+
+    #[test]
+    fn crafted_offsets_do_not_overflow() {
+        // Program headers past the end of the address space are truncated, not wrapped
+        let e = annotate_bytes(
+            "phoff",
+            Kind::Core,
+            None,
+            &core64(u64::MAX - 8, &[(1, 0, 0, 0); 2]),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+        // A note segment ending past u64::MAX and a segment loaded at the top of memory
+        let a = annotate_bytes(
+            "vaddr",
+            Kind::Core,
+            None,
+            &core64(64, &[(4, u64::MAX - 4, 0, 8), (1, 8, u64::MAX - 1, 16)]),
+        )
+        .unwrap();
+        assert_eq!(
+            label(&a, 8),
+            Some("segment LOAD 1 r-- vaddr 0xfffffffffffffffe..0xffffffffffffffff")
+        );
+        assert_eq!(a.mappings.len(), 1);
+    }
+
+    #[test]
+    fn prpsinfo_32bit() {
+        // The 32 bit prpsinfo is 124 bytes, name at 28 and arguments at 44
+        let mut desc = [0u8; 124];
+        desc[28..33].copy_from_slice(b"sleep");
+        desc[44..53].copy_from_slice(b"sleep 100");
+
+        let a = annotate_bytes("prpsinfo", Kind::Core, None, &core32(3, &desc)).unwrap();
+        assert_eq!(
+            label(&a, 84),
+            Some("note CORE NT_PRPSINFO sleep (sleep 100), 124 bytes")
+        );
+    }
+}
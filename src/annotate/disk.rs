@@ -0,0 +1,608 @@
+//! Raw disk image annotation.
+//!
+//! Labels the MBR and its extended partition chain, or the GPT behind a
+//! protective MBR, marking where every partition starts and ends. Each
+//! partition, or the whole image when it holds a single filesystem, is probed
+//! for a FAT, ext2/3/4 or NTFS superblock whose fields and regions are labeled.
+
+use std::io;
+
+use super::{Annotations, Reader, not_format, uuid};
+
+/// Sector size of MBR partitioned disks.
+const SECTOR: u64 = 512;
+
+/// Logical partitions followed at most, guarding against cyclic chains.
+const MAX_LOGICAL: u32 = 128;
+
+/// MBR partition types and their names.
+const MBR_TYPES: [(u8, &str); 18] = [
+    (0x01, "FAT12"),
+    (0x04, "FAT16 <32M"),
+    (0x05, "Extended"),
+    (0x06, "FAT16"),
+    (0x07, "NTFS/exFAT"),
+    (0x0B, "FAT32"),
+    (0x0C, "FAT32 LBA"),
+    (0x0E, "FAT16 LBA"),
+    (0x0F, "Extended LBA"),
+    (0x82, "Linux swap"),
+    (0x83, "Linux"),
+    (0x85, "Linux extended"),
+    (0x8E, "Linux LVM"),
+    (0xA5, "FreeBSD"),
+    (0xAF, "HFS+"),
+    (0xEE, "GPT protective"),
+    (0xEF, "EFI system"),
+    (0xFD, "Linux RAID"),
+];
+
+/// GPT partition type GUIDs and their names.
+const GPT_TYPES: [(&str, &str); 11] = [
+    ("C12A7328-F81F-11D2-BA4B-00A0C93EC93B", "EFI system"),
+    ("21686148-6449-6E6F-744E-656564454649", "BIOS boot"),
+    ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "Linux filesystem"),
+    (
+        "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+        "Linux root (x86-64)",
+    ),
+    ("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F", "Linux swap"),
+    ("E6D6D379-F507-44C2-A23C-238F2A3DF928", "Linux LVM"),
+    (
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7",
+        "Microsoft basic data",
+    ),
+    ("E3C9E316-0B5C-4DB8-817D-F92DF00215AE", "Microsoft reserved"),
+    ("DE94BBA4-06D1-4D40-A16A-BFD50179D6AC", "Windows recovery"),
+    ("48465300-0000-11AA-AA11-00306543ECAC", "HFS+"),
+    ("7C3457EF-0000-11AA-AA11-00306543ECAC", "APFS"),
+];
+
+/// Annotates the disk image behind `r`.
+pub fn annotate(r: &mut Reader) -> io::Result<Annotations> {
+    let mut a = Annotations::default();
+
+    // A filesystem at the very start means the image is not partitioned
+    if volume(r, 0, &mut a)? {
+        return Ok(a);
+    }
+
+    if r.len < SECTOR || r.u16(0x1FE)? != 0xAA55 {
+        return Err(not_format("a partitioned disk image or known filesystem"));
+    }
+
+    a.label(0, "MBR boot code");
+    a.label(0x1B8, format!("disk signature 0x{:08x}", r.u32(0x1B8)?));
+    a.label(0x1FE, "MBR boot signature");
+
+    for i in 0..4 {
+        let at = 0x1BE + i * 16;
+
+        let (active, kind) = (r.u8(at)? == 0x80, r.u8(at + 4)?);
+        let (lba, sectors) = (r.u32(at + 8)? as u64, r.u32(at + 12)? as u64);
+        if kind == 0 {
+            continue;
+        }
+
+        a.label(
+            at,
+            format!(
+                "partition entry {}: {}{}, sectors {}..{}",
+                i + 1,
+                mbr_type(kind),
+                if active { ", active" } else { "" },
+                lba,
+                lba + sectors
+            ),
+        );
+
+        match kind {
+            0xEE => gpt(r, &mut a)?,
+            0x05 | 0x0F | 0x85 => logical(r, lba, &mut a)?,
+            _ => partition(
+                r,
+                i as u32 + 1,
+                &mbr_type(kind),
+                lba * SECTOR,
+                sectors * SECTOR,
+                &mut a,
+            )?,
+        }
+    }
+
+    Ok(a)
+}
+
+/// Follows the chain of extended boot records of the extended partition at `first`.
+///
+/// Logical partitions are numbered from 5, as Linux does.
+fn logical(r: &Reader, first: u64, a: &mut Annotations) -> io::Result<()> {
+    let mut ebr = first;
+
+    for number in 5..5 + MAX_LOGICAL {
+        let at = ebr * SECTOR;
+        if at + SECTOR > r.len || r.u16(at + 0x1FE)? != 0xAA55 {
+            break;
+        }
+        a.label(at, format!("extended boot record of partition {}", number));
+
+        // The first entry is relative to this record, the second to the extended partition
+        let kind = r.u8(at + 0x1BE + 4)?;
+        let (lba, sectors) = (
+            r.u32(at + 0x1BE + 8)? as u64,
+            r.u32(at + 0x1BE + 12)? as u64,
+        );
+        if kind != 0 {
+            partition(
+                r,
+                number,
+                &mbr_type(kind),
+                (ebr + lba) * SECTOR,
+                sectors * SECTOR,
+                a,
+            )?;
+        }
+
+        let next = r.u32(at + 0x1CE + 8)? as u64;
+        if r.u8(at + 0x1CE + 4)? == 0 || next == 0 {
+            break;
+        }
+        ebr = first + next;
+    }
+
+    Ok(())
+}
+
+/// Labels the GPT header and partition entries behind a protective MBR.
+fn gpt(r: &Reader, a: &mut Annotations) -> io::Result<()> {
+    // The header is at LBA 1, whose offset depends on the sector size
+    let Some(sector) = [512, 4096]
+        .into_iter()
+        .find(|&s| s + 92 <= r.len && r.bytes(s, 8).is_ok_and(|b| b == b"EFI PART"))
+    else {
+        return Ok(());
+    };
+
+    let revision = r.u32(sector + 8)?;
+    let backup = r.u64(sector + 32)?;
+    let (first, last) = (r.u64(sector + 40)?, r.u64(sector + 48)?);
+    let entries = r.u64(sector + 72)?;
+    let (count, size) = (r.u32(sector + 80)? as u64, r.u32(sector + 84)? as u64);
+
+    a.label(
+        sector,
+        format!(
+            "GPT header: revision {}.{}, {} byte sectors, backup at LBA {}",
+            revision >> 16,
+            revision & 0xFFFF,
+            sector,
+            backup
+        ),
+    );
+    a.label(sector + 40, format!("usable LBAs {}..={}", first, last));
+    a.label(
+        sector + 56,
+        format!("disk GUID {}", guid(&r.array(sector + 56)?)),
+    );
+    a.label(
+        sector + 72,
+        format!(
+            "partition entries at LBA {}: {} of {} bytes",
+            entries, count, size
+        ),
+    );
+    a.label(backup.saturating_mul(sector), "backup GPT header");
+
+    if size < 128 {
+        return Ok(());
+    }
+
+    for i in 0..count {
+        let at = entries.saturating_mul(sector).saturating_add(i * size);
+        if at.saturating_add(size) > r.len {
+            break;
+        }
+
+        let kind = guid(&r.array(at)?);
+        if kind == "00000000-0000-0000-0000-000000000000" {
+            continue;
+        }
+        let (start, end) = (r.u64(at + 32)?, r.u64(at + 40)?);
+
+        // Names are up to 36 UTF-16 code units
+        let units: Vec<u16> = r
+            .bytes(at + 56, 72)?
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&u| u != 0)
+            .collect();
+        let name = String::from_utf16_lossy(&units);
+
+        let what = match GPT_TYPES.iter().find(|&&(g, _)| g == kind) {
+            Some(&(_, what)) => what.to_owned(),
+            None => kind,
+        };
+
+        a.label(
+            at,
+            format!(
+                "GPT entry {}: '{}' {}, LBAs {}..={}",
+                i + 1,
+                name,
+                what,
+                start,
+                end
+            ),
+        );
+        partition(
+            r,
+            i as u32 + 1,
+            &what,
+            start.saturating_mul(sector),
+            end.saturating_add(1)
+                .saturating_sub(start)
+                .saturating_mul(sector),
+            a,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Labels the boundaries of partition `number` and any filesystem within it.
+fn partition(
+    r: &Reader,
+    number: u32,
+    what: &str,
+    start: u64,
+    len: u64,
+    a: &mut Annotations,
+) -> io::Result<()> {
+    a.label(start, format!("partition {} start: {}", number, what));
+    a.label(
+        start.saturating_add(len),
+        format!("end of partition {}", number),
+    );
+
+    volume(r, start, a)?;
+
+    Ok(())
+}
+
+/// Labels a FAT, ext2/3/4 or NTFS filesystem at `base`, returning whether one was found.
+fn volume(r: &Reader, base: u64, a: &mut Annotations) -> io::Result<bool> {
+    if base.saturating_add(SECTOR) > r.len {
+        return Ok(false);
+    }
+
+    if r.bytes(base + 3, 8)? == b"NTFS    " {
+        ntfs(r, base, a)?;
+        return Ok(true);
+    }
+
+    // FAT has no magic, so check the BPB holds sensible values instead
+    let bps = r.u16(base + 11)?;
+    let spc = r.u8(base + 13)?;
+    if matches!(bps, 512 | 1024 | 2048 | 4096)
+        && spc.is_power_of_two()
+        && r.u16(base + 14)? > 0
+        && matches!(r.u8(base + 16)?, 1 | 2)
+        && r.u16(base + 0x1FE)? == 0xAA55
+    {
+        fat(r, base, a)?;
+        return Ok(true);
+    }
+
+    if base + 2048 <= r.len && r.u16(base + 1024 + 0x38)? == 0xEF53 {
+        ext(r, base, a)?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Labels the FAT boot sector at `base` and the regions it lays out.
+fn fat(r: &Reader, base: u64, a: &mut Annotations) -> io::Result<()> {
+    let bps = r.u16(base + 11)? as u64;
+    let spc = r.u8(base + 13)? as u64;
+    let reserved = r.u16(base + 14)? as u64;
+    let fats = r.u8(base + 16)? as u64;
+    let roots = r.u16(base + 17)? as u64;
+    let total = match r.u16(base + 19)? {
+        0 => r.u32(base + 32)? as u64,
+        n => n as u64,
+    };
+    let fat_size = match r.u16(base + 22)? {
+        0 => r.u32(base + 36)? as u64,
+        n => n as u64,
+    };
+
+    // The variant follows from the cluster count alone
+    let root_sectors = (roots * 32).div_ceil(bps);
+    let first_data = reserved + fats * fat_size + root_sectors;
+    let clusters = total.saturating_sub(first_data) / spc;
+    let bits = match clusters {
+        0..4085 => 12,
+        4085..65525 => 16,
+        _ => 32,
+    };
+
+    a.label(
+        base,
+        format!(
+            "FAT{} boot sector: OEM '{}'",
+            bits,
+            r.str(base + 3, 8)?.trim_end()
+        ),
+    );
+    a.label(
+        base + 11,
+        format!(
+            "BPB: {} bytes per sector, {} sectors per cluster, {} reserved sectors, {} FATs of {} sectors, {} sectors, {} clusters",
+            bps, spc, reserved, fats, fat_size, total, clusters
+        ),
+    );
+
+    // The extended BPB moves past the FAT32 only fields
+    let ebpb = if bits == 32 { 0x40 } else { 0x24 };
+    if r.u8(base + ebpb + 2)? == 0x29 {
+        let serial = r.u32(base + ebpb + 3)?;
+
+        a.label(
+            base + ebpb,
+            format!(
+                "extended BPB: serial {:04X}-{:04X}, label '{}', type '{}'",
+                serial >> 16,
+                serial & 0xFFFF,
+                r.str(base + ebpb + 7, 11)?.trim_end(),
+                r.str(base + ebpb + 18, 8)?.trim_end()
+            ),
+        );
+    }
+    a.label(base + 0x1FE, "boot signature");
+
+    if bits == 32 {
+        let root = r.u32(base + 0x2C)?;
+        let (info, backup) = (r.u16(base + 0x30)? as u64, r.u16(base + 0x32)? as u64);
+
+        a.label(base + 0x2C, format!("root directory at cluster {}", root));
+        if info != 0 && info != 0xFFFF {
+            a.label(base + info * bps, "FSInfo sector");
+        }
+        if backup != 0 && backup != 0xFFFF {
+            a.label(base + backup * bps, "backup boot sector");
+        }
+    }
+
+    for i in 0..fats {
+        a.label(
+            base + (reserved + i * fat_size) * bps,
+            format!("FAT {}, {} sectors", i + 1, fat_size),
+        );
+    }
+    if root_sectors > 0 {
+        a.label(
+            base + (reserved + fats * fat_size) * bps,
+            format!("root directory, {} entries", roots),
+        );
+    }
+    a.label(base + first_data * bps, "data region, cluster 2");
+
+    Ok(())
+}
+
+/// Labels the ext2/3/4 superblock of the filesystem at `base`.
+fn ext(r: &Reader, base: u64, a: &mut Annotations) -> io::Result<()> {
+    let sb = base + 1024;
+
+    let inodes = r.u32(sb)?;
+    let first_data = r.u32(sb + 0x14)? as u64;
+    let log = r.u32(sb + 0x18)?;
+    let (blocks_per_group, inodes_per_group) = (r.u32(sb + 0x20)? as u64, r.u32(sb + 0x28)?);
+    let revision = r.u32(sb + 0x4C)?;
+    let (compat, incompat, ro_compat) = (r.u32(sb + 0x5C)?, r.u32(sb + 0x60)?, r.u32(sb + 0x64)?);
+
+    // 64 bit filesystems keep the high half of the block count apart
+    let mut blocks = r.u32(sb + 4)? as u64;
+    if incompat & 0x80 != 0 {
+        blocks |= (r.u32(sb + 0x150)? as u64) << 32;
+    }
+
+    let block = 1024u64
+        .checked_shl(log)
+        .filter(|_| log <= 6)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid ext block size at 0x{:016x}", sb + 0x18),
+            )
+        })?;
+    let inode_size = if revision >= 1 {
+        r.u16(sb + 0x58)?
+    } else {
+        128
+    };
+
+    // Extents, 64 bit and flexible groups are ext4 only, a journal makes ext3
+    let kind = if incompat & (0x40 | 0x80 | 0x200) != 0 {
+        "ext4"
+    } else if compat & 0x4 != 0 {
+        "ext3"
+    } else {
+        "ext2"
+    };
+
+    a.label(base, "boot block");
+    a.label(
+        sb,
+        format!(
+            "{} superblock: {} blocks of {} bytes, {} inodes of {} bytes",
+            kind, blocks, block, inodes, inode_size
+        ),
+    );
+    a.label(
+        sb + 0x20,
+        format!(
+            "{} blocks and {} inodes per group",
+            blocks_per_group, inodes_per_group
+        ),
+    );
+    a.label(sb + 0x38, format!("magic 0xEF53, revision {}", revision));
+    a.label(
+        sb + 0x5C,
+        format!(
+            "features compat 0x{:x}, incompat 0x{:x}, ro_compat 0x{:x}",
+            compat, incompat, ro_compat
+        ),
+    );
+    a.label(
+        sb + 0x68,
+        format!(
+            "UUID {}, volume name '{}'",
+            uuid(&r.array(sb + 0x68)?),
+            r.str(sb + 0x78, 16)?
+        ),
+    );
+
+    if blocks_per_group > 0 {
+        let groups = blocks.saturating_sub(first_data).div_ceil(blocks_per_group);
+
+        a.label(
+            base + (first_data + 1) * block,
+            format!("block group descriptors, {} groups", groups),
+        );
+    }
+
+    Ok(())
+}
+
+/// Labels the NTFS boot sector at `base` and the MFT it points at.
+fn ntfs(r: &Reader, base: u64, a: &mut Annotations) -> io::Result<()> {
+    let bps = r.u16(base + 0x0B)? as u64;
+
+    // Large cluster sizes are stored as a negative power of two
+    let spc = match r.u8(base + 0x0D)? {
+        v @ 0x81.. => 1u64.checked_shl(256 - v as u32).unwrap_or(0),
+        v => v as u64,
+    };
+
+    // Nonsense sizes leave the regions derived from them unlabeled
+    let cluster = bps.checked_mul(spc).filter(|&c| c > 0);
+
+    let total = r.u64(base + 0x28)?;
+    let (mft, mirror) = (r.u64(base + 0x30)?, r.u64(base + 0x38)?);
+    let record = match r.u8(base + 0x40)? as i8 {
+        v @ ..0 => 1u64.checked_shl(v.unsigned_abs() as u32),
+        v => cluster.and_then(|c| c.checked_mul(v as u64)),
+    };
+
+    a.label(base, "NTFS boot sector");
+    a.label(
+        base + 0x0B,
+        format!(
+            "BPB: {} bytes per sector, {} sectors per cluster, {} sectors",
+            bps, spc, total
+        ),
+    );
+    a.label(
+        base + 0x30,
+        match record {
+            Some(record) => format!(
+                "$MFT at cluster {}, $MFTMirr at cluster {}, {} byte records",
+                mft, mirror, record
+            ),
+            None => format!("$MFT at cluster {}, $MFTMirr at cluster {}", mft, mirror),
+        },
+    );
+    a.label(
+        base + 0x48,
+        format!("volume serial {:016X}", r.u64(base + 0x48)?),
+    );
+    a.label(base + 0x1FE, "boot signature");
+
+    if let Some(cluster) = cluster {
+        a.label(base.saturating_add(mft.saturating_mul(cluster)), "$MFT");
+        a.label(
+            base.saturating_add(mirror.saturating_mul(cluster)),
+            "$MFTMirr",
+        );
+    }
+
+    // The backup boot sector sits just past the sectors the volume counts
+    a.label(
+        base.saturating_add(total.saturating_mul(bps)),
+        "NTFS backup boot sector",
+    );
+
+    Ok(())
+}
+
+/// Formats a GUID stored with its first three fields little endian.
+fn guid(b: &[u8; 16]) -> String {
+    let mut b = *b;
+    b[..4].reverse();
+    b[4..6].reverse();
+    b[6..8].reverse();
+
+    uuid(&b)
+}
+
+/// Names an MBR partition type.
+fn mbr_type(kind: u8) -> String {
+    match MBR_TYPES.iter().find(|&&(k, _)| k == kind) {
+        Some(&(_, name)) => name.to_owned(),
+        None => format!("type 0x{:02x}", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::annotate::{
+        Kind,
+        tests::{annotate_bytes, label},
+    };
+
+    /// Builds an NTFS boot sector with the given sizes and `$MFT` at cluster 4.
+    fn ntfs(bps: u16, spc: u8, record: u8) -> Vec<u8> {
+        let mut boot = vec![0u8; 512];
+        boot[3..11].copy_from_slice(b"NTFS    ");
+        boot[0x0B..0x0D].copy_from_slice(&bps.to_le_bytes());
+        boot[0x0D] = spc;
+        boot[0x28..0x30].copy_from_slice(&16u64.to_le_bytes());
+        boot[0x30..0x38].copy_from_slice(&4u64.to_le_bytes());
+        boot[0x38..0x40].copy_from_slice(&2u64.to_le_bytes());
+        boot[0x40] = record;
+        boot[0x1FE..].copy_from_slice(&[0x55, 0xAA]);
+
+        boot
+    }
+
+    #[test]
+    fn ntfs_record_size() {
+        let a = annotate_bytes("ntfs", Kind::Disk, None, &ntfs(512, 8, 0xF6)).unwrap();
+        assert_eq!(
+            label(&a, 0x30),
+            Some("$MFT at cluster 4, $MFTMirr at cluster 2, 1024 byte records")
+        );
+        assert_eq!(label(&a, 4 * 4096), Some("$MFT"));
+    }
+
+    #[test]
+    fn ntfs_nonsense_sizes_are_skipped() {
+        // A record size of 2^128 bytes does not fit
+        let a = annotate_bytes("ntfs-record", Kind::Disk, None, &ntfs(512, 8, 0x80)).unwrap();
+        assert_eq!(
+            label(&a, 0x30),
+            Some("$MFT at cluster 4, $MFTMirr at cluster 2")
+        );
+        assert_eq!(label(&a, 4 * 4096), Some("$MFT"));
+
+        // Neither does a cluster of 2 * 2^63 bytes, nor records counted in it
+        let a = annotate_bytes("ntfs-cluster", Kind::Disk, None, &ntfs(2, 0xC1, 1)).unwrap();
+        assert_eq!(
+            label(&a, 0x30),
+            Some("$MFT at cluster 4, $MFTMirr at cluster 2")
+        );
+        assert!(a.labels.iter().all(|(_, text)| text != "$MFT"));
+    }
+}
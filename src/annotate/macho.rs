@@ -0,0 +1,472 @@
+//! Mach-O annotation.
+//!
+//! Labels the Mach-O header, every load command, and the segments and sections
+//! they describe, mapping each segment to its virtual address. Fat (universal)
+//! binaries have their arch table labeled and every slice annotated, or only
+//! the slice named by `--arch`.
+
+use std::io;
+
+use super::{Annotations, Mapping, Reader, not_format, uuid};
+
+/// Load command of 32 bit segments.
+const LC_SEGMENT: u32 = 0x1;
+
+/// Load command of 64 bit segments.
+const LC_SEGMENT_64: u32 = 0x19;
+
+/// Load command names.
+const COMMANDS: [(u32, &str); 34] = [
+    (0x1, "LC_SEGMENT"),
+    (0x2, "LC_SYMTAB"),
+    (0x4, "LC_THREAD"),
+    (0x5, "LC_UNIXTHREAD"),
+    (0xB, "LC_DYSYMTAB"),
+    (0xC, "LC_LOAD_DYLIB"),
+    (0xD, "LC_ID_DYLIB"),
+    (0xE, "LC_LOAD_DYLINKER"),
+    (0xF, "LC_ID_DYLINKER"),
+    (0x19, "LC_SEGMENT_64"),
+    (0x1B, "LC_UUID"),
+    (0x1D, "LC_CODE_SIGNATURE"),
+    (0x1E, "LC_SEGMENT_SPLIT_INFO"),
+    (0x21, "LC_ENCRYPTION_INFO"),
+    (0x22, "LC_DYLD_INFO"),
+    (0x24, "LC_VERSION_MIN_MACOSX"),
+    (0x25, "LC_VERSION_MIN_IPHONEOS"),
+    (0x26, "LC_FUNCTION_STARTS"),
+    (0x27, "LC_DYLD_ENVIRONMENT"),
+    (0x29, "LC_DATA_IN_CODE"),
+    (0x2A, "LC_SOURCE_VERSION"),
+    (0x2B, "LC_DYLIB_CODE_SIGN_DRS"),
+    (0x2C, "LC_ENCRYPTION_INFO_64"),
+    (0x2D, "LC_LINKER_OPTION"),
+    (0x2E, "LC_LINKER_OPTIMIZATION_HINT"),
+    (0x32, "LC_BUILD_VERSION"),
+    (0x8000_0018, "LC_LOAD_WEAK_DYLIB"),
+    (0x8000_001C, "LC_RPATH"),
+    (0x8000_001F, "LC_REEXPORT_DYLIB"),
+    (0x8000_0022, "LC_DYLD_INFO_ONLY"),
+    (0x8000_0023, "LC_LOAD_UPWARD_DYLIB"),
+    (0x8000_0028, "LC_MAIN"),
+    (0x8000_0033, "LC_DYLD_EXPORTS_TRIE"),
+    (0x8000_0034, "LC_DYLD_CHAINED_FIXUPS"),
+];
+
+/// Load commands pointing at a blob of `__LINKEDIT` data, and what the blob is.
+const LINKEDIT_DATA: [(u32, &str); 8] = [
+    (0x1D, "code signature"),
+    (0x1E, "segment split info"),
+    (0x26, "function starts"),
+    (0x29, "data in code"),
+    (0x2B, "dylib code signing DRs"),
+    (0x2E, "linker optimization hints"),
+    (0x8000_0033, "exports trie"),
+    (0x8000_0034, "chained fixups"),
+];
+
+/// Annotates the Mach-O or fat binary behind `r`, limited to the `arch` slice if given.
+pub fn annotate(r: &mut Reader, arch: Option<&str>) -> io::Result<Annotations> {
+    let mut a = Annotations::default();
+
+    if r.len < 8 {
+        return Err(not_format("a Mach-O file"));
+    }
+
+    let wide = match r.array::<4>(0)? {
+        [0xCA, 0xFE, 0xBA, 0xBE] => false,
+        [0xCA, 0xFE, 0xBA, 0xBF] => true,
+        _ => {
+            let name = thin(r, 0, &mut a)?;
+
+            if let Some(arch) = arch.filter(|&arch| arch != name) {
+                return Err(no_arch(arch));
+            }
+
+            return Ok(a);
+        }
+    };
+
+    // Java class files share the fat magic, their version makes for a large count
+    r.big = true;
+    let count = r.u32(4)?;
+    if count == 0 || count > 20 {
+        return Err(not_format("a Mach-O file"));
+    }
+
+    a.label(
+        0,
+        format!(
+            "fat header: {} bit, {} architectures",
+            if wide { 64 } else { 32 },
+            count
+        ),
+    );
+
+    let mut found = false;
+    for i in 0..count as u64 {
+        let at = 8 + i * if wide { 32 } else { 20 };
+
+        let (cputype, subtype) = (r.u32(at)?, r.u32(at + 4)?);
+        let (offset, size, align) = if wide {
+            (r.u64(at + 8)?, r.u64(at + 16)?, r.u32(at + 24)?)
+        } else {
+            (
+                r.u32(at + 8)? as u64,
+                r.u32(at + 12)? as u64,
+                r.u32(at + 16)?,
+            )
+        };
+        let name = arch_name(cputype, subtype);
+
+        a.label(
+            at,
+            format!(
+                "fat arch {}: {} offset 0x{:x} size 0x{:x} align 2^{}",
+                i, name, offset, size, align
+            ),
+        );
+
+        if arch.is_some_and(|arch| arch != name) {
+            continue;
+        }
+        found = true;
+
+        // Slices start with their own header, whose byte order may differ
+        thin(r, offset, &mut a)?;
+        a.label(
+            offset.saturating_add(size),
+            format!("end of {} slice", name),
+        );
+
+        if arch.is_some() {
+            a.slice = Some((offset, offset.saturating_add(size)));
+        }
+        r.big = true;
+    }
+
+    match arch {
+        Some(arch) if !found => Err(no_arch(arch)),
+        _ => Ok(a),
+    }
+}
+
+/// Annotates the single architecture Mach-O at `base`, returning its arch name.
+fn thin(r: &mut Reader, base: u64, a: &mut Annotations) -> io::Result<String> {
+    let (wide, big) = match r.array::<4>(base)? {
+        [0xFE, 0xED, 0xFA, 0xCE] => (false, true),
+        [0xFE, 0xED, 0xFA, 0xCF] => (true, true),
+        [0xCE, 0xFA, 0xED, 0xFE] => (false, false),
+        [0xCF, 0xFA, 0xED, 0xFE] => (true, false),
+        _ => return Err(not_format("a Mach-O file")),
+    };
+    r.big = big;
+
+    let (cputype, subtype) = (r.u32(base + 4)?, r.u32(base + 8)?);
+    let filetype = r.u32(base + 12)?;
+    let (ncmds, sizeofcmds) = (r.u32(base + 16)?, r.u32(base + 20)?);
+    let name = arch_name(cputype, subtype);
+
+    a.label(
+        base,
+        format!(
+            "Mach-O header: {} bit {} endian, type {}, arch {}, {} load commands",
+            if wide { 64 } else { 32 },
+            if big { "big" } else { "little" },
+            file_type(filetype),
+            name,
+            ncmds
+        ),
+    );
+
+    let commands = base + if wide { 32 } else { 28 };
+    let end = commands + sizeofcmds as u64;
+    let mut at = commands;
+
+    for i in 0..ncmds {
+        let (cmd, size) = (r.u32(at)?, r.u32(at + 4)? as u64);
+        if size < 8 || at + size > end {
+            return Err(invalid(at));
+        }
+
+        let what = match COMMANDS.iter().find(|&&(c, _)| c == cmd) {
+            Some(&(_, name)) => name.to_owned(),
+            None => format!("0x{:x}", cmd),
+        };
+        let details = match cmd {
+            LC_SEGMENT | LC_SEGMENT_64 => {
+                segment(r, base, at, cmd == LC_SEGMENT_64, &name, a)?;
+                format!(" {}", r.str(at + 8, 16)?)
+            }
+            0x2 => {
+                let (symoff, nsyms) = (r.u32(at + 8)?, r.u32(at + 12)?);
+                let (stroff, strsize) = (r.u32(at + 16)?, r.u32(at + 20)?);
+
+                a.label(
+                    base + symoff as u64,
+                    format!("symbol table: {} entries", nsyms),
+                );
+                a.label(
+                    base + stroff as u64,
+                    format!("string table: {} bytes", strsize),
+                );
+                format!(" {} symbols", nsyms)
+            }
+            0xC | 0xD | 0xE | 0xF | 0x27 | 0x8000_0018 | 0x8000_001C | 0x8000_001F
+            | 0x8000_0023 => {
+                // The name follows the fixed fields, at an offset given first
+                let offset = r.u32(at + 8)? as u64;
+
+                format!(" {}", r.str(at + offset, size.saturating_sub(offset))?)
+            }
+            0x1B => format!(" {}", uuid(&r.array::<16>(at + 8)?)),
+            0x8000_0028 => {
+                let entry = r.u64(at + 8)?;
+
+                a.label(base.saturating_add(entry), "entry point");
+                format!(" entry at 0x{:x}", entry)
+            }
+            _ => match LINKEDIT_DATA.iter().find(|&&(c, _)| c == cmd) {
+                Some(&(_, blob)) => {
+                    let (offset, len) = (r.u32(at + 8)?, r.u32(at + 12)?);
+
+                    if len > 0 {
+                        a.label(base + offset as u64, format!("{}: {} bytes", blob, len));
+                    }
+                    String::new()
+                }
+                None => String::new(),
+            },
+        };
+
+        a.label(
+            at,
+            format!("load command {}: {}{}, {} bytes", i, what, details, size),
+        );
+        at += size;
+    }
+
+    Ok(name)
+}
+
+/// Labels a segment command at `at` and its sections, and maps the segment.
+fn segment(
+    r: &Reader,
+    base: u64,
+    at: u64,
+    wide: bool,
+    arch: &str,
+    a: &mut Annotations,
+) -> io::Result<()> {
+    let name = r.str(at + 8, 16)?;
+    let (vmaddr, vmsize, fileoff, filesize) = if wide {
+        (
+            r.u64(at + 24)?,
+            r.u64(at + 32)?,
+            r.u64(at + 40)?,
+            r.u64(at + 48)?,
+        )
+    } else {
+        (
+            r.u32(at + 24)? as u64,
+            r.u32(at + 28)? as u64,
+            r.u32(at + 32)? as u64,
+            r.u32(at + 36)? as u64,
+        )
+    };
+    let (prot, nsects) = if wide {
+        (r.u32(at + 60)?, r.u32(at + 64)?)
+    } else {
+        (r.u32(at + 44)?, r.u32(at + 48)?)
+    };
+
+    if filesize > 0 {
+        let offset = base.checked_add(fileoff).ok_or_else(|| invalid(at))?;
+
+        a.label(
+            offset,
+            format!(
+                "segment {} {} vmaddr 0x{:x}..0x{:x}",
+                name,
+                protection(prot),
+                vmaddr,
+                vmaddr.saturating_add(vmsize)
+            ),
+        );
+        a.label(
+            offset.saturating_add(filesize),
+            format!("end of segment {}", name),
+        );
+        a.mappings.push(Mapping {
+            offset,
+            len: filesize,
+            vaddr: vmaddr,
+            name: format!("{} {} {}", arch, name, protection(prot)),
+        });
+    }
+
+    // Section headers follow the segment command
+    let (first, size) = if wide { (72, 80) } else { (56, 68) };
+    for i in 0..nsects as u64 {
+        let s = at + first + i * size;
+
+        let sectname = r.str(s, 16)?;
+        let (addr, len, offset, flags) = if wide {
+            (
+                r.u64(s + 32)?,
+                r.u64(s + 40)?,
+                r.u32(s + 48)?,
+                r.u32(s + 64)?,
+            )
+        } else {
+            (
+                r.u32(s + 32)? as u64,
+                r.u32(s + 36)? as u64,
+                r.u32(s + 40)?,
+                r.u32(s + 56)?,
+            )
+        };
+
+        a.label(s, format!("section header {},{}", name, sectname));
+
+        // Zero fill sections take no room in the file
+        if offset == 0 || matches!(flags & 0xFF, 0x1 | 0xC | 0x12) {
+            continue;
+        }
+        a.label(
+            base + offset as u64,
+            format!(
+                "section {},{} addr 0x{:x}, {} bytes",
+                name, sectname, addr, len
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+/// Builds the error reported for a load command at `at` that cannot be right.
+fn invalid(at: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid load command at 0x{:016x}", at),
+    )
+}
+
+/// Builds the error reported when the selected architecture is missing.
+fn no_arch(arch: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("input has no {} architecture", arch),
+    )
+}
+
+/// Names an architecture the way `lipo` does.
+fn arch_name(cputype: u32, subtype: u32) -> String {
+    match (cputype, subtype & 0xFF) {
+        (7, _) => "i386".to_owned(),
+        (0x0100_0007, 8) => "x86_64h".to_owned(),
+        (0x0100_0007, _) => "x86_64".to_owned(),
+        (12, 9) => "armv7".to_owned(),
+        (12, 11) => "armv7s".to_owned(),
+        (12, _) => "arm".to_owned(),
+        (0x0100_000C, 2) => "arm64e".to_owned(),
+        (0x0100_000C, _) => "arm64".to_owned(),
+        (0x0200_000C, _) => "arm64_32".to_owned(),
+        (18, _) => "ppc".to_owned(),
+        (0x0100_0012, _) => "ppc64".to_owned(),
+        (c, s) => format!("cpu 0x{:x}/0x{:x}", c, s),
+    }
+}
+
+/// Names a Mach-O file type.
+fn file_type(filetype: u32) -> String {
+    match filetype {
+        1 => "OBJECT",
+        2 => "EXECUTE",
+        4 => "CORE",
+        5 => "PRELOAD",
+        6 => "DYLIB",
+        7 => "DYLINKER",
+        8 => "BUNDLE",
+        9 => "DYLIB_STUB",
+        10 => "DSYM",
+        11 => "KEXT_BUNDLE",
+        12 => "FILESET",
+        t => return format!("0x{:x}", t),
+    }
+    .to_owned()
+}
+
+/// Formats VM protection as `rwx`.
+fn protection(prot: u32) -> String {
+    [(1, 'r'), (2, 'w'), (4, 'x')]
+        .iter()
+        .map(|&(bit, c)| if prot & bit != 0 { c } else { '-' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::annotate::{
+        Kind,
+        tests::{annotate_bytes, label},
+    };
+
+    /// Builds a fat binary whose x86_64 slice at 0x1000 holds the given load commands.
+    fn fat(commands: &[&[u8]]) -> Vec<u8> {
+        let mut fat = vec![0u8; 0x1100];
+        fat[..8].copy_from_slice(b"\xca\xfe\xba\xbe\0\0\0\x01");
+        for (i, field) in [0x0100_0007, 3, 0x1000, 0x100, 12].iter().enumerate() {
+            fat[8 + i * 4..12 + i * 4].copy_from_slice(&u32::to_be_bytes(*field));
+        }
+
+        let mut thin = b"\xcf\xfa\xed\xfe".to_vec();
+        for field in [0x0100_0007, 3, 2, commands.len() as u32, 0, 0, 0] {
+            thin.extend(field.to_le_bytes());
+        }
+        for &command in commands {
+            thin.extend(command);
+        }
+        let sizeofcmds = thin.len() as u32 - 32;
+        thin[20..24].copy_from_slice(&sizeofcmds.to_le_bytes());
+
+        fat[0x1000..0x1000 + thin.len()].copy_from_slice(&thin);
+        fat
+    }
+
+    #[test]
+    fn entry_point_near_u64_max() {
+        let mut main = 0x8000_0028u32.to_le_bytes().to_vec();
+        main.extend(24u32.to_le_bytes());
+        main.extend((u64::MAX - 0x10).to_le_bytes());
+        main.extend(0u64.to_le_bytes());
+
+        // The slice offset would push the entry point past u64::MAX
+        let a = annotate_bytes("lc-main", Kind::Macho, None, &fat(&[&main])).unwrap();
+        assert_eq!(
+            label(&a, 0x1020),
+            Some("load command 0: LC_MAIN entry at 0xffffffffffffffef, 24 bytes")
+        );
+        assert_eq!(label(&a, u64::MAX), Some("entry point"));
+    }
+
+    #[test]
+    fn segment_offset_near_u64_max() {
+        let mut segment = 0x19u32.to_le_bytes().to_vec();
+        segment.extend(72u32.to_le_bytes());
+        segment.extend(b"__TEXT\0\0\0\0\0\0\0\0\0\0");
+        for field in [u64::MAX, 0x10, u64::MAX - 0x10, 1] {
+            segment.extend(field.to_le_bytes());
+        }
+        segment.extend([0u8; 16]);
+
+        let e = annotate_bytes("segment", Kind::Macho, None, &fat(&[&segment]))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "invalid load command at 0x0000000000001020");
+    }
+}
@@ -0,0 +1,238 @@
+//! Structure annotation backing `--annotate`.
+//!
+//! Each format parser reads the headers of the input and returns labels for
+//! the dump, along with the mappings between file offsets and the virtual
+//! addresses they are loaded at, which `--seek va:ADDRESS` resolves through.
+
+mod coredump;
+mod disk;
+mod macho;
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+/// Formats `--annotate` understands.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    /// ELF core dump, or any other ELF file with program headers.
+    Core,
+
+    /// Mach-O binary, thin or fat.
+    Macho,
+
+    /// Raw disk image, partitioned or holding a single filesystem.
+    Disk,
+}
+
+impl Kind {
+    /// Parses a format name as given on the command line.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "core" => Some(Kind::Core),
+            "macho" => Some(Kind::Macho),
+            "disk" => Some(Kind::Disk),
+            _ => None,
+        }
+    }
+}
+
+/// A run of the file loaded at a virtual address.
+pub struct Mapping {
+    /// Offset of the run within the file.
+    pub offset: u64,
+
+    /// Length of the run within the file.
+    pub len: u64,
+
+    /// Virtual address of the first byte.
+    pub vaddr: u64,
+
+    /// Description of the run, e.g. the segment and the file it maps.
+    pub name: String,
+}
+
+/// Everything a format parser found in the input.
+#[derive(Default)]
+pub struct Annotations {
+    /// Notes attached to offsets, in no particular order.
+    pub labels: Vec<(u64, String)>,
+
+    /// Loaded runs of the file, in no particular order.
+    pub mappings: Vec<Mapping>,
+
+    /// Start and end offsets the dump is limited to, e.g. a selected slice.
+    pub slice: Option<(u64, u64)>,
+}
+
+impl Annotations {
+    /// Attaches `text` to `offset`.
+    fn label(&mut self, offset: u64, text: impl Into<String>) {
+        self.labels.push((offset, text.into()));
+    }
+}
+
+/// Parses the headers of `file` as `kind`, limited to the `arch` slice of fat binaries.
+pub fn annotate(kind: Kind, arch: Option<&str>, file: &File) -> io::Result<Annotations> {
+    let mut reader = Reader {
+        file,
+        len: file.metadata()?.len(),
+        big: false,
+    };
+
+    match kind {
+        Kind::Core => coredump::annotate(&mut reader),
+        Kind::Macho => macho::annotate(&mut reader, arch),
+        Kind::Disk => disk::annotate(&mut reader),
+    }
+}
+
+/// Bounds checked, endian aware reads of header fields.
+struct Reader<'a> {
+    /// File being annotated.
+    file: &'a File,
+
+    /// Length of the file.
+    len: u64,
+
+    /// Whether multi-byte fields are big endian.
+    big: bool,
+}
+
+impl Reader<'_> {
+    /// Reads `len` bytes at `offset`, failing if they run past the end of the file.
+    fn bytes(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        if offset.checked_add(len).is_none_or(|end| end > self.len) {
+            return Err(truncated(offset));
+        }
+
+        let mut buf = vec![0u8; len as usize];
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Reads a fixed size array at `offset`.
+    fn array<const N: usize>(&self, offset: u64) -> io::Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(&self.bytes(offset, N as u64)?);
+
+        Ok(array)
+    }
+
+    /// Reads a single byte at `offset`.
+    fn u8(&self, offset: u64) -> io::Result<u8> {
+        Ok(self.array::<1>(offset)?[0])
+    }
+
+    /// Reads a 16 bit field at `offset`.
+    fn u16(&self, offset: u64) -> io::Result<u16> {
+        let b = self.array(offset)?;
+
+        Ok(if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    /// Reads a 32 bit field at `offset`.
+    fn u32(&self, offset: u64) -> io::Result<u32> {
+        let b = self.array(offset)?;
+
+        Ok(if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    /// Reads a 64 bit field at `offset`.
+    fn u64(&self, offset: u64) -> io::Result<u64> {
+        let b = self.array(offset)?;
+
+        Ok(if self.big {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+
+    /// Reads a 32 or 64 bit field at `offset`, depending on `wide`.
+    fn word(&self, offset: u64, wide: bool) -> io::Result<u64> {
+        if wide {
+            self.u64(offset)
+        } else {
+            self.u32(offset).map(u64::from)
+        }
+    }
+
+    /// Reads a NUL padded string of at most `len` bytes at `offset`.
+    fn str(&self, offset: u64, len: u64) -> io::Result<String> {
+        let bytes = self.bytes(offset, len)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
+
+/// Builds the error reported when a structure at `offset` runs past the end of the file.
+fn truncated(offset: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("truncated structure at 0x{:016x}", offset),
+    )
+}
+
+/// Builds the error reported when the input is not of the annotated format.
+fn not_format(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("input is not {}", what))
+}
+
+/// Formats a UUID in its usual dashed form, bytes in order.
+fn uuid(b: &[u8; 16]) -> String {
+    let hex: String = b.iter().map(|b| format!("{:02X}", b)).collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{env, fs, io, process};
+
+    use super::{Annotations, Kind, annotate};
+
+    /// Annotates `bytes` as `kind` through a scratch file named after `name`.
+    pub(crate) fn annotate_bytes(
+        name: &str,
+        kind: Kind,
+        arch: Option<&str>,
+        bytes: &[u8],
+    ) -> io::Result<Annotations> {
+        let path = env::temp_dir().join(format!("dumpx-test-{}-{}", process::id(), name));
+        fs::write(&path, bytes)?;
+
+        let result = fs::File::open(&path).and_then(|file| annotate(kind, arch, &file));
+        fs::remove_file(&path)?;
+
+        result
+    }
+
+    /// Returns the text of the label at `offset`, if any.
+    pub(crate) fn label(a: &Annotations, offset: u64) -> Option<&str> {
+        a.labels
+            .iter()
+            .find(|&&(at, _)| at == offset)
+            .map(|(_, text)| text.as_str())
+    }
+}
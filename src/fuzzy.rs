@@ -0,0 +1,200 @@
+//! Context triggered piecewise hashing backing `--fuzzy-hash`.
+//!
+//! This follows the spamsum algorithm used by ssdeep: a rolling hash over a
+//! small window picks content defined block boundaries, and each block adds one
+//! base64 character of its FNV style hash to the signature. Files sharing most
+//! of their content end up with signatures sharing long runs of characters.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Smallest block size tried.
+const MIN_BLOCKSIZE: u64 = 3;
+
+/// Largest block size, doubled 30 times from the smallest as in ssdeep.
+const MAX_BLOCKSIZE: u64 = MIN_BLOCKSIZE << 30;
+
+/// Target length of the first signature.
+const SPAMSUM_LENGTH: usize = 64;
+
+/// Size of the rolling hash window.
+const ROLLING_WINDOW: usize = 7;
+
+/// Initial value of the block hash.
+const HASH_INIT: u32 = 0x2802_1967;
+
+/// Multiplier of the block hash.
+const HASH_PRIME: u32 = 0x0100_0193;
+
+/// Alphabet of the signature characters.
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// I/O buffer size for reading chunks from the input.
+const IO_BUF_SIZE: usize = 64 * 1024;
+
+/// Rolling hash over the last `ROLLING_WINDOW` bytes.
+#[derive(Default)]
+struct Roll {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl Roll {
+    /// Pushes `c` into the window and returns the updated hash.
+    fn push(&mut self, c: u8) -> u32 {
+        let c = c as u32;
+        let slot = &mut self.window[self.n % ROLLING_WINDOW];
+
+        self.h2 = self.h2.wrapping_sub(self.h1);
+        self.h2 = self.h2.wrapping_add(ROLLING_WINDOW as u32 * c);
+
+        self.h1 = self.h1.wrapping_add(c);
+        self.h1 = self.h1.wrapping_sub(*slot as u32);
+
+        *slot = c as u8;
+        self.n += 1;
+
+        self.h3 = (self.h3 << 5) ^ c;
+
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+/// Hashes `len` bytes of `input` into a `BLOCKSIZE:SIG1:SIG2` signature.
+///
+/// The input is rewound and read again whenever the block size guessed from
+/// `len` yields too short a signature. Inputs too large for the largest block
+/// size are rejected, as ssdeep does.
+pub fn hash<R: Read + Seek>(mut input: R, len: u64) -> io::Result<String> {
+    let max_len = MAX_BLOCKSIZE * SPAMSUM_LENGTH as u64;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("input too large to fuzzy hash, at most {} bytes", max_len),
+        ));
+    }
+
+    let mut block_size = MIN_BLOCKSIZE;
+    while block_size * (SPAMSUM_LENGTH as u64) < len {
+        block_size *= 2;
+    }
+
+    let mut io_buf = vec![0u8; IO_BUF_SIZE];
+
+    loop {
+        input.seek(SeekFrom::Start(0))?;
+
+        let mut roll = Roll::default();
+        let (mut h, mut h2, mut h3) = (0, HASH_INIT, HASH_INIT);
+
+        // Signatures at the block size and at twice the block size
+        let mut sig1 = Vec::with_capacity(SPAMSUM_LENGTH);
+        let mut sig2 = Vec::with_capacity(SPAMSUM_LENGTH / 2);
+        let (mut tail1, mut tail2) = (None, None);
+
+        loop {
+            let n = input.read(&mut io_buf)?;
+            if n == 0 {
+                break;
+            }
+
+            for &c in &io_buf[..n] {
+                h = roll.push(c);
+                h2 = h2.wrapping_mul(HASH_PRIME) ^ c as u32;
+                h3 = h3.wrapping_mul(HASH_PRIME) ^ c as u32;
+
+                // Once a signature is full its last character tracks the latest block
+                if h as u64 % block_size == block_size - 1 {
+                    let c = B64[(h2 % 64) as usize];
+                    if sig1.len() < SPAMSUM_LENGTH - 1 {
+                        sig1.push(c);
+                        h2 = HASH_INIT;
+                    } else {
+                        tail1 = Some(c);
+                    }
+                }
+
+                if h as u64 % (block_size * 2) == block_size * 2 - 1 {
+                    let c = B64[(h3 % 64) as usize];
+                    if sig2.len() < SPAMSUM_LENGTH / 2 - 1 {
+                        sig2.push(c);
+                        h3 = HASH_INIT;
+                    } else {
+                        tail2 = Some(c);
+                    }
+                }
+            }
+        }
+
+        // Boundaries found at this size, not counting the trailing character
+        let blocks = sig1.len();
+
+        // A trailing partial block contributes the final character
+        if h != 0 {
+            tail1 = Some(B64[(h2 % 64) as usize]);
+            tail2 = Some(B64[(h3 % 64) as usize]);
+        }
+        sig1.extend(tail1);
+        sig2.extend(tail2);
+
+        // Too few boundaries at this size, retry with smaller blocks
+        if block_size > MIN_BLOCKSIZE && blocks < SPAMSUM_LENGTH / 2 {
+            block_size /= 2;
+            continue;
+        }
+
+        return Ok(format!(
+            "{}:{}:{}",
+            block_size,
+            String::from_utf8_lossy(&sig1),
+            String::from_utf8_lossy(&sig2)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind};
+
+    use super::{MAX_BLOCKSIZE, SPAMSUM_LENGTH, hash};
+
+    fn digest(input: &[u8]) -> String {
+        hash(Cursor::new(input), input.len() as u64).unwrap()
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(digest(b""), "3::");
+    }
+
+    #[test]
+    fn huge_input() {
+        let max = MAX_BLOCKSIZE * SPAMSUM_LENGTH as u64;
+
+        // The largest block size must not overflow, even when retried down to the smallest
+        assert_eq!(hash(Cursor::new(b""), max).unwrap(), "3::");
+        assert_eq!(
+            hash(Cursor::new(b""), max + 1).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            hash(Cursor::new(b""), u64::MAX).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    // Digests published with python-ssdeep, computed by libfuzzy itself
+    #[test]
+    fn matches_ssdeep() {
+        assert_eq!(
+            digest(b"Also called fuzzy hashes, Ctph can match inputs that have homologies."),
+            "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C"
+        );
+        assert_eq!(
+            digest(b"Also called fuzzy hashes, CTPH can match inputs that have homologies."),
+            "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C"
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Multi-file byte pattern search backing `dumpx grep`.
+//!
+//! The file list is collected up front, then searched by a pool of worker
+//! threads. Results are written in file list order regardless of which worker
+//! finishes first, so the output is stable between runs.
+//!
+//! Like grep, `dumpx grep` exits with 0 when anything matched, 1 when nothing
+//! did, and 2 when a path could not be searched.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use crate::{DumpX, search};
+
+/// Search options of `dumpx grep`.
+pub struct Grep {
+    /// Bytes to search for.
+    pub pattern: Vec<u8>,
+
+    /// Files and directories to search.
+    pub paths: Vec<PathBuf>,
+
+    /// Whether directories are searched recursively instead of skipped.
+    pub recursive: bool,
+
+    /// Optional file name pattern, with `*` and `?` wildcards.
+    pub glob: Option<String>,
+
+    /// Smallest file size searched, in bytes.
+    pub min_size: Option<u64>,
+
+    /// Largest file size searched, in bytes.
+    pub max_size: Option<u64>,
+
+    /// Number of worker threads. If `0`, one per available CPU.
+    pub jobs: usize,
+}
+
+impl Grep {
+    /// Creates a search with no pattern, paths or filters yet.
+    pub fn new() -> Self {
+        Grep {
+            pattern: Vec::new(),
+            paths: Vec::new(),
+            recursive: false,
+            glob: None,
+            min_size: None,
+            max_size: None,
+            jobs: 0,
+        }
+    }
+
+    /// Searches every selected file and writes `path:offset` hits to `out`.
+    ///
+    /// When `dumpx` has search context set, the matching lines are dumped after
+    /// each file's hits. Unreadable files are reported on stderr and skipped.
+    ///
+    /// Returns the exit status grep would: 0 when anything matched, 1 when
+    /// nothing did, and 2 when any path could not be searched.
+    pub fn run<W: Write>(&self, dumpx: &DumpX, out: W) -> io::Result<i32> {
+        let mut out = io::BufWriter::new(out);
+
+        let mut files = Vec::new();
+        let mut failed = 0;
+        for path in &self.paths {
+            failed += self.collect(path, true, &mut files);
+        }
+
+        let jobs = match self.jobs {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(files.len().max(1));
+
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                let tx = tx.clone();
+                let (next, files) = (&next, &files);
+
+                scope.spawn(move || {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(i) else {
+                            break;
+                        };
+
+                        // The receiver only goes away once output failed
+                        if tx.send((i, self.search(dumpx, path))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            // Buffer early finishers until every file before them is written
+            let mut pending = BTreeMap::new();
+            let mut written = 0;
+            let mut any = false;
+
+            for (i, result) in rx {
+                pending.insert(i, result);
+
+                while let Some(result) = pending.remove(&written) {
+                    match result {
+                        Ok(hits) if hits.is_empty() => {}
+                        Ok(hits) => {
+                            if any && dumpx.context.is_some() {
+                                writeln!(out, "--")?;
+                            }
+                            out.write_all(&hits)?;
+                            any = true;
+                        }
+                        Err(e) => {
+                            eprintln!("Error: {}: {}", files[written].display(), e);
+                            failed += 1;
+                        }
+                    }
+                    written += 1;
+                }
+            }
+            out.flush()?;
+
+            Ok(match (failed, any) {
+                (0, true) => 0,
+                (0, false) => 1,
+                _ => 2,
+            })
+        })
+    }
+
+    /// Searches a single file, returning its formatted hits.
+    fn search(&self, dumpx: &DumpX, path: &Path) -> io::Result<Vec<u8>> {
+        let file = File::open(path)?;
+        let matches = search::find(BufReader::new(&file), &self.pattern)?;
+
+        let mut hits = Vec::new();
+        for offset in &matches {
+            writeln!(hits, "{}:0x{:016x}", path.display(), offset)?;
+        }
+
+        if !matches.is_empty() && dumpx.context.is_some() {
+            dumpx.dump_matches(&file, &matches, self.pattern.len() as u64, &mut hits)?;
+        }
+
+        Ok(hits)
+    }
+
+    /// Adds `path` to `files` if selected, descending into directories when recursive.
+    ///
+    /// Paths given on the command line, the `top` ones, are followed wherever
+    /// they link. Below them, symbolic links to directories are skipped to avoid
+    /// cycles. Returns the number of paths that could not be searched.
+    fn collect(&self, path: &Path, top: bool, files: &mut Vec<PathBuf>) -> usize {
+        let report = |e: io::Error| {
+            eprintln!("Error: {}: {}", path.display(), e);
+            1
+        };
+
+        let meta = match fs::symlink_metadata(path).and_then(|m| {
+            // Links to files are always searched
+            if m.file_type().is_symlink() {
+                fs::metadata(path).map(|t| if top || t.is_file() { t } else { m })
+            } else {
+                Ok(m)
+            }
+        }) {
+            Ok(meta) => meta,
+            Err(e) => return report(e),
+        };
+
+        let mut failed = 0;
+
+        if meta.is_dir() {
+            if !self.recursive {
+                eprintln!("Error: {}: is a directory (use -R)", path.display());
+                return 1;
+            }
+
+            let mut entries =
+                match fs::read_dir(path).and_then(|d| d.collect::<io::Result<Vec<_>>>()) {
+                    Ok(entries) => entries,
+                    Err(e) => return report(e),
+                };
+            entries.sort_by_key(|e| e.file_name());
+
+            for entry in entries {
+                failed += self.collect(&entry.path(), false, files);
+            }
+        } else if meta.is_file() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+            let selected = self
+                .glob
+                .as_ref()
+                .is_none_or(|g| glob(g.as_bytes(), name.as_bytes()))
+                && self.min_size.is_none_or(|min| meta.len() >= min)
+                && self.max_size.is_none_or(|max| meta.len() <= max);
+
+            if selected {
+                files.push(path.to_path_buf());
+            }
+        }
+
+        failed
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` is any run and `?` any single byte.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+
+    // Last `*` seen and the name position it currently absorbs up to
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` absorb one more byte and retry
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        glob(pattern.as_bytes(), name.as_bytes())
+    }
+
+    #[test]
+    fn literal_and_wildcards() {
+        assert!(matches("core", "core"));
+        assert!(!matches("core", "core.1"));
+        assert!(matches("core.?", "core.1"));
+        assert!(!matches("core.?", "core."));
+        assert!(matches("*.bin", "firmware.bin"));
+        assert!(!matches("*.bin", "firmware.bin.bak"));
+        assert!(matches("*", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("*ab", "aaab"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("**x*", "x"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Edit journal backing `--in-place` and `--revert`.
+//!
+//! Every in-place modification is appended to a text file next to the edited
+//! file before the bytes are written, one `OFFSET OLD_HEX NEW_HEX` line per
+//! edit. Reverting replays the journal backwards.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::DumpX;
+
+/// A single recorded modification.
+pub struct Entry {
+    /// Offset of the first modified byte.
+    pub offset: u64,
+
+    /// Bytes before the modification.
+    pub old: Vec<u8>,
+
+    /// Bytes after the modification.
+    pub new: Vec<u8>,
+}
+
+/// Append only writer for a journal file.
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Suffix appended to the edited file's name to form the journal's.
+    const SUFFIX: &'static str = ".dumpx-journal";
+
+    /// First line of every journal, identifying the format.
+    const MAGIC: &'static str = "# dumpx journal";
+
+    /// Returns the journal path used for edits of `path`.
+    pub fn path_for(path: &Path) -> PathBuf {
+        DumpX::sidecar(path, Self::SUFFIX)
+    }
+
+    /// Opens the journal at `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", Self::MAGIC)?;
+        }
+
+        Ok(Journal { file })
+    }
+
+    /// Records a modification. Must be called before the bytes are written.
+    pub fn record(&mut self, offset: u64, old: &[u8], new: &[u8]) -> io::Result<()> {
+        writeln!(
+            self.file,
+            "0x{:016x} {} {}",
+            offset,
+            DumpX::to_hex(old),
+            DumpX::to_hex(new)
+        )?;
+
+        // Make sure the entry is durable before the edit it describes
+        self.file.sync_data()
+    }
+
+    /// Reads all entries of the journal at `path`, oldest first.
+    pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: invalid journal entry", path.display(), line),
+            )
+        };
+
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        if lines.next().transpose()?.as_deref() != Some(Self::MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is not a dumpx journal", path.display()),
+            ));
+        }
+
+        let mut entries = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let entry = (|| {
+                let offset = DumpX::parse_offset(fields.next()?)?;
+                let old = DumpX::parse_hex(fields.next()?)?;
+                let new = DumpX::parse_hex(fields.next()?)?;
+
+                (fields.next().is_none() && old.len() == new.len()).then_some(Entry {
+                    offset,
+                    old,
+                    new,
+                })
+            })()
+            // Line numbers are 1 based and the magic line was already consumed
+            .ok_or_else(|| invalid(i + 2))?;
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /// Undoes every entry of the journal at `journal` on `target`, newest first.
+    ///
+    /// All entries are checked against the current contents before anything is
+    /// written, so a mismatching journal leaves the target untouched. The journal
+    /// is removed once reverted. Returns the number of entries undone.
+    pub fn revert(journal: &Path, target: &Path) -> io::Result<usize> {
+        let entries = Journal::read(journal)?;
+        let mut file = OpenOptions::new().read(true).write(true).open(target)?;
+
+        // Simulate the revert over an overlay of pending writes to validate it
+        let mut overlay = HashMap::new();
+        for entry in entries.iter().rev() {
+            let mut current = vec![0u8; entry.new.len()];
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut current)?;
+
+            for (i, b) in current.iter_mut().enumerate() {
+                if let Some(&pending) = overlay.get(&(entry.offset + i as u64)) {
+                    *b = pending;
+                }
+            }
+
+            if current != entry.new {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "bytes at 0x{:016x} no longer match the journal, nothing reverted",
+                        entry.offset
+                    ),
+                ));
+            }
+
+            for (i, &b) in entry.old.iter().enumerate() {
+                overlay.insert(entry.offset + i as u64, b);
+            }
+        }
+
+        for entry in entries.iter().rev() {
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.write_all(&entry.old)?;
+        }
+        file.sync_data()?;
+
+        fs::remove_file(journal)?;
+
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::ErrorKind,
+        path::{Path, PathBuf},
+        process,
+    };
+
+    use super::Journal;
+
+    /// Scratch file path under the temporary directory, removed when dropped.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            Scratch(env::temp_dir().join(format!("dumpx-test-{}-{}", process::id(), name)))
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Journals `entries` into `journal`, as an in-place edit would have.
+    fn record(journal: &Path, entries: &[(u64, &[u8], &[u8])]) {
+        let mut j = Journal::open(journal).unwrap();
+        for &(offset, old, new) in entries {
+            j.record(offset, old, new).unwrap();
+        }
+    }
+
+    #[test]
+    fn overlapping_entries_revert_newest_first() {
+        let (target, journal) = (Scratch::new("overlap"), Scratch::new("overlap-journal"));
+
+        // The second edit rewrites bytes of the first, which only match through the overlay
+        record(&journal.0, &[(0, b"AAAA", b"BBBB"), (1, b"BB", b"CC")]);
+        fs::write(&target.0, b"BCCB").unwrap();
+
+        assert_eq!(Journal::revert(&journal.0, &target.0).unwrap(), 2);
+        assert_eq!(fs::read(&target.0).unwrap(), b"AAAA");
+        assert!(!journal.0.exists());
+    }
+
+    #[test]
+    fn mismatch_leaves_target_unchanged() {
+        let (target, journal) = (Scratch::new("mismatch"), Scratch::new("mismatch-journal"));
+
+        // The newest entry matches, the oldest no longer does
+        record(&journal.0, &[(0, b"AA", b"BB"), (2, b"AA", b"CC")]);
+        fs::write(&target.0, b"XBCC").unwrap();
+
+        let e = Journal::revert(&journal.0, &target.0).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(fs::read(&target.0).unwrap(), b"XBCC");
+        assert!(journal.0.exists());
+    }
+
+    #[test]
+    fn malformed_entries_report_line() {
+        let journal = Scratch::new("malformed");
+
+        for (content, line) in [
+            ("# dumpx journal\n0x0 41 42\n\n# note\n0x1 41\n", 5),
+            ("# dumpx journal\n0x0 4142 43\n", 2),
+            ("# dumpx journal\n0x0 41 42 43\n", 2),
+            ("# dumpx journal\nzz 41 42\n", 2),
+        ] {
+            fs::write(&journal.0, content).unwrap();
+
+            let e = Journal::read(&journal.0).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+            assert_eq!(
+                e.to_string(),
+                format!("{}:{}: invalid journal entry", journal.0.display(), line)
+            );
+        }
+
+        fs::write(&journal.0, "0x0 41 42\n").unwrap();
+        assert!(
+            Journal::read(&journal.0)
+                .err()
+                .unwrap()
+                .to_string()
+                .ends_with("is not a dumpx journal")
+        );
+    }
+}
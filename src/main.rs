@@ -10,22 +10,145 @@
 //!
 //! Options:
 //!   -o, --output <OUTPUT_FILE_PATH>    Write to a new file (default: stdout)
+//!   --baseline <BYTE>                  Dim bytes equal to BYTE and highlight the rest
+//!   --color-rules <RULES_FILE_PATH>    Style bytes, offsets and patterns as the rules say
+//!   --map <MAP_FILE_PATH>              Label offsets with the symbols of a map file
+//!   --map-base <ADDRESS>               Load address of the input, subtracted from map addresses (default: 0)
+//!   --seek <OFFSET|SYMBOL[+OFFSET]>    Start the dump at an offset or symbol
+//!   --annotate <core|macho|disk>       Label the structures of the input's format
+//!   --arch <NAME>                      Annotate and dump only this slice of a fat Mach-O
+//!   --seek va:<ADDRESS>                Start the dump at a virtual address of the annotation
+//!   --render png <IMAGE_FILE_PATH>     Render bytes as pixels into a new PNG image
+//!   --width <PIXELS>                   Image width when rendering (default: 256)
+//!   --palette <gray|class>             Pixel coloring when rendering (default: class)
+//!   --replace <FIND_HEX=REPLACE_HEX>   Copy the input with bytes substituted (repeatable)
+//!   --max-count <COUNT>                Stop replacing after COUNT replacements
+//!   --in-place                         Replace within the input, journaling each edit
+//!   --revert <JOURNAL_FILE_PATH>       Undo the in-place edits recorded in a journal
+//!   --fuzzy-hash                       Print an ssdeep style similarity hash of the input
+//!   --similarity <REFERENCE_FILE_PATH> Report how much of the input is reused from a reference
+//!   --interactive                      Explore the input with line based commands
+//!   -A, --after-context <LINES>        Dump LINES lines after each search match
+//!   -B, --before-context <LINES>       Dump LINES lines before each search match
+//!   -C, --context <LINES>              Dump LINES lines around each search match
+//!
+//! dumpx grep --hex <HEX> <PATH>... [OPTIONS]
+//!
+//! Grep options:
+//!   --hex <HEX>                        Bytes to search for, e.g. "CA FE BA BE"
+//!   -R, --recursive                    Search directories recursively
+//!   --glob <PATTERN>                   Only search files whose name matches PATTERN
+//!   --min-size <BYTES>                 Only search files of at least BYTES
+//!   --max-size <BYTES>                 Only search files of at most BYTES
+//!   -j, --jobs <COUNT>                 Number of parallel workers (default: CPU count)
 //! ```
 
 use std::{
+    collections::BTreeMap,
     env,
-    fs::File,
-    io::{self, Read, Write},
-    path::PathBuf,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     process,
 };
 
+mod annotate;
+mod fuzzy;
+mod grep;
+mod journal;
+mod patch;
+mod png;
+mod repl;
+mod rules;
+mod search;
+mod session;
+mod similarity;
+mod symbols;
+
+use annotate::Mapping;
+use grep::Grep;
+use journal::Journal;
+use patch::Patcher;
+use png::Palette;
+use repl::Repl;
+use rules::Rules;
+
+/// What to produce from the input file.
+enum Mode {
+    /// Hex + ASCII dump written to the output.
+    Dump,
+
+    /// Image with one pixel per byte, written to `path`.
+    Render {
+        path: PathBuf,
+        width: u32,
+        palette: Palette,
+    },
+
+    /// Input with bytes substituted, written to the output or in place.
+    Replace { patcher: Patcher, in_place: bool },
+
+    /// Undo of the in-place edits recorded in the journal at the given path.
+    Revert(PathBuf),
+
+    /// Context triggered piecewise hash of the input, written to the output.
+    FuzzyHash,
+
+    /// Regions of the input shared with the reference file at the given path.
+    Similarity(PathBuf),
+
+    /// Line based command prompt on stdin and stdout.
+    Interactive,
+
+    /// Search of many files for a byte pattern, hits written to the output.
+    Grep(Grep),
+}
+
 struct DumpX {
     /// Path to the input file to read and dump.
     input: PathBuf,
 
     /// Optional path to the output file. If `None`, writes to stdout.
     output: Option<PathBuf>,
+
+    /// Optional baseline byte. Matching bytes are dimmed, others highlighted.
+    baseline: Option<u8>,
+
+    /// Optional path to a coloring rules file.
+    color_rules: Option<PathBuf>,
+
+    /// Coloring rules loaded from `color_rules`, applied over the baseline styling.
+    rules: Option<Rules>,
+
+    /// Optional path to a symbol map labeling offsets.
+    map: Option<PathBuf>,
+
+    /// Address the input is loaded at, subtracted from map addresses to give offsets.
+    map_base: u64,
+
+    /// Symbol names and their offsets, loaded from `map`.
+    symbols: BTreeMap<String, u64>,
+
+    /// Optional offset or symbol to start the dump at.
+    seek: Option<String>,
+
+    /// Optional format whose structures are labeled.
+    annotate: Option<annotate::Kind>,
+
+    /// Optional architecture selecting a slice of a fat Mach-O, e.g. `arm64`.
+    arch: Option<String>,
+
+    /// Runs of the file loaded at virtual addresses, found by the annotation.
+    mappings: Vec<Mapping>,
+
+    /// Lines dumped before and after each search match. If `None`, only offsets are listed.
+    context: Option<(u64, u64)>,
+
+    /// Notes attached to offsets, printed on their own line before the line holding them.
+    labels: BTreeMap<u64, Vec<String>>,
+
+    /// What to produce from the input file.
+    mode: Mode,
 }
 
 impl DumpX {
@@ -47,6 +170,65 @@ impl DumpX {
         "\n",
         "  -o, --output <OUTPUT_FILE_PATH>  Write to a new file  [Optional]  (Default: stdout)",
         "\n",
+        "  --baseline <BYTE>                Dim bytes equal to BYTE, highlight the rest  [Optional]",
+        "\n",
+        "  --color-rules <RULES_FILE_PATH>  Style bytes, offsets and patterns as the rules say  [Optional]",
+        "\n",
+        "  --map <MAP_FILE_PATH>            Label offsets with the symbols of a map file  [Optional]",
+        "\n",
+        "  --map-base <ADDRESS>             Load address of the input, subtracted from map addresses  [Optional]  (Default: 0)",
+        "\n",
+        "  --seek <OFFSET|SYMBOL[+OFFSET]>  Start the dump at an offset or symbol  [Optional]",
+        "\n",
+        "  --annotate <core|macho|disk>     Label the structures of the input's format  [Optional]",
+        "\n",
+        "  --arch <NAME>                    Annotate and dump only this slice of a fat Mach-O  [Optional]",
+        "\n",
+        "  --seek va:<ADDRESS>              Start the dump at a virtual address of the annotation  [Optional]",
+        "\n",
+        "  --render png <IMAGE_FILE_PATH>   Render bytes as pixels into a new PNG image  [Optional]",
+        "\n",
+        "  --width <PIXELS>                 Image width when rendering  [Optional]  (Default: 256)",
+        "\n",
+        "  --palette <gray|class>           Pixel coloring when rendering  [Optional]  (Default: class)",
+        "\n",
+        "  --replace <FIND_HEX=REPLACE_HEX> Copy the input with bytes substituted  [Optional]  [Repeatable]",
+        "\n",
+        "  --max-count <COUNT>              Stop replacing after COUNT replacements  [Optional]",
+        "\n",
+        "  --in-place                       Replace within the input, journaling each edit  [Optional]",
+        "\n",
+        "  --revert <JOURNAL_FILE_PATH>     Undo the in-place edits recorded in a journal  [Optional]",
+        "\n",
+        "  --fuzzy-hash                     Print an ssdeep style similarity hash of the input  [Optional]",
+        "\n",
+        "  --similarity <REFERENCE_FILE_PATH>  Report how much of the input is reused from a reference  [Optional]",
+        "\n",
+        "  --interactive                    Explore the input with line based commands  [Optional]",
+        "\n",
+        "  -A, --after-context <LINES>      Dump LINES lines after each search match  [Optional]",
+        "\n",
+        "  -B, --before-context <LINES>     Dump LINES lines before each search match  [Optional]",
+        "\n",
+        "  -C, --context <LINES>            Dump LINES lines around each search match  [Optional]",
+        "\n",
+        "\n",
+        "Usage: dumpx grep --hex <HEX> <PATH>... [OPTIONS]",
+        "\n\n",
+        "Grep options:",
+        "\n",
+        "  --hex <HEX>                      Bytes to search for, e.g. \"CA FE BA BE\"",
+        "\n",
+        "  -R, --recursive                  Search directories recursively  [Optional]",
+        "\n",
+        "  --glob <PATTERN>                 Only search files whose name matches PATTERN  [Optional]",
+        "\n",
+        "  --min-size <BYTES>               Only search files of at least BYTES  [Optional]",
+        "\n",
+        "  --max-size <BYTES>               Only search files of at most BYTES  [Optional]",
+        "\n",
+        "  -j, --jobs <COUNT>               Number of parallel workers  [Optional]  (Default: CPU count)",
+        "\n",
     );
 
     /// Number of bytes per output line.
@@ -73,10 +255,25 @@ impl DumpX {
     const ASCII_SECTION: usize = 2 + Self::WIDTH + 1;
 
     /// Total buffer size needed per line: offset + hex section + ASCII section.
+    ///
+    /// Styled lines may grow past this, it is only the initial capacity.
     const LINE_BUF_SIZE: usize = Self::OFFSET_LEN + Self::HEX_SECTION + Self::ASCII_SECTION;
+
+    /// Escape sequence resetting any styling.
+    const RESET: &'static [u8] = b"\x1b[0m";
+
+    /// Escape sequence for bytes equal to the baseline.
+    const DIM: &'static [u8] = b"\x1b[2m";
+
+    /// Escape sequence for bytes deviating from the baseline.
+    const HIGHLIGHT: &'static [u8] = b"\x1b[1;33m";
+
     /// I/O buffer size for reading chunks from the file.
     const IO_BUF_SIZE: usize = 64 * 1024;
 
+    /// Default image width in pixels for `--render`.
+    const RENDER_WIDTH: u32 = 256;
+
     /// Lookup table for converting a 4 bit value to its hex ASCII representation.
     const NIBBLE_LUT: [u8; 16] = *b"0123456789abcdef";
 
@@ -102,6 +299,25 @@ impl DumpX {
 
         let mut input = PathBuf::new();
         let mut output = None;
+        let mut baseline = None;
+        let mut color_rules = None;
+        let mut map = None;
+        let mut map_base = None;
+        let mut seek = None;
+        let mut annotate = None;
+        let mut arch = None;
+        let mut render = None;
+        let mut width = Self::RENDER_WIDTH;
+        let mut palette = Palette::Class;
+        let mut replacements = Vec::new();
+        let mut max_count = None;
+        let mut in_place = false;
+        let mut revert = None;
+        let mut fuzzy_hash = false;
+        let mut reference = None;
+        let mut interactive = false;
+        let mut context: Option<(u64, u64)> = None;
+        let mut grep = None;
 
         // If no args provided, show usage header and exit
         if args.peek().is_none() {
@@ -110,6 +326,11 @@ impl DumpX {
             process::exit(0);
         }
 
+        // A leading `grep` selects the multi-file search and its own options
+        if args.next_if(|a| a == "grep").is_some() {
+            grep = Some(Grep::new());
+        }
+
         // Iterate through arguments.
         while let Some(arg) = args.next() {
             match arg.as_str() {
@@ -118,6 +339,195 @@ impl DumpX {
                     output = Some(PathBuf::from(args.next().ok_or("--output requires file")?));
                 }
 
+                // Handle baseline flag and its byte value
+                "--baseline" => {
+                    let value = args.next().ok_or("--baseline requires byte")?;
+
+                    baseline = Some(Self::parse_byte(&value).ok_or("invalid --baseline byte")?);
+                }
+
+                // Handle color rules flag and its file path
+                "--color-rules" => {
+                    color_rules = Some(PathBuf::from(
+                        args.next().ok_or("--color-rules requires file")?,
+                    ));
+                }
+
+                // Handle map flag and its file path
+                "--map" => {
+                    map = Some(PathBuf::from(args.next().ok_or("--map requires file")?));
+                }
+
+                // Handle map base flag and its load address
+                "--map-base" => {
+                    let value = args.next().ok_or("--map-base requires address")?;
+
+                    map_base =
+                        Some(Self::parse_offset(&value).ok_or("invalid --map-base address")?);
+                }
+
+                // Handle seek flag and its offset or symbol, resolved once the map is loaded
+                "--seek" => {
+                    seek = Some(args.next().ok_or("--seek requires offset or symbol")?);
+                }
+
+                // Handle annotate flag and its format name
+                "--annotate" => {
+                    let value = args.next().ok_or("--annotate requires format")?;
+
+                    annotate =
+                        Some(annotate::Kind::parse(&value).ok_or("unknown --annotate format")?);
+                }
+
+                // Handle arch flag and its architecture name
+                "--arch" => {
+                    arch = Some(args.next().ok_or("--arch requires name")?);
+                }
+
+                // Handle render flag, its format and image path
+                "--render" => {
+                    if args.next().ok_or("--render requires format")? != "png" {
+                        return Err("unsupported --render format");
+                    }
+
+                    render = Some(PathBuf::from(args.next().ok_or("--render requires file")?));
+                }
+
+                // Handle image width and its pixel count
+                "--width" => {
+                    width = args
+                        .next()
+                        .ok_or("--width requires pixels")?
+                        .parse()
+                        .ok()
+                        .filter(|&w| w > 0)
+                        .ok_or("invalid --width pixels")?;
+                }
+
+                // Handle palette flag and its name
+                "--palette" => {
+                    let value = args.next().ok_or("--palette requires name")?;
+
+                    palette = Palette::parse(&value).ok_or("unknown --palette name")?;
+                }
+
+                // Handle replace flag and its find/replace hex pair
+                "--replace" => {
+                    let value = args.next().ok_or("--replace requires FIND=REPLACE")?;
+                    let (find, replace) = value
+                        .split_once('=')
+                        .ok_or("--replace requires FIND=REPLACE")?;
+
+                    let find = Self::parse_hex(find).ok_or("invalid --replace hex")?;
+                    let replace = Self::parse_hex(replace).ok_or("invalid --replace hex")?;
+
+                    if find.is_empty() || find.len() != replace.len() {
+                        return Err("--replace lengths must match");
+                    }
+
+                    replacements.push((find, replace));
+                }
+
+                // Handle replacement limit and its count
+                "--max-count" => {
+                    let value = args.next().ok_or("--max-count requires count")?;
+
+                    max_count = Some(value.parse().map_err(|_| "invalid --max-count count")?);
+                }
+
+                // Handle in-place flag
+                "--in-place" => in_place = true,
+
+                // Handle revert flag and its journal path
+                "--revert" => {
+                    revert = Some(PathBuf::from(args.next().ok_or("--revert requires file")?));
+                }
+
+                // Handle fuzzy hash flag
+                "--fuzzy-hash" => fuzzy_hash = true,
+
+                // Handle similarity flag and its reference file path
+                "--similarity" => {
+                    reference = Some(PathBuf::from(
+                        args.next().ok_or("--similarity requires file")?,
+                    ));
+                }
+
+                // Handle interactive flag
+                "--interactive" => interactive = true,
+
+                // Handle search context flags and their line counts
+                "-A" | "--after-context" | "-B" | "--before-context" | "-C" | "--context" => {
+                    let lines = args
+                        .next()
+                        .ok_or("context flags require lines")?
+                        .parse()
+                        .map_err(|_| "invalid context lines")?;
+
+                    let (before, after) = context.get_or_insert((0, 0));
+                    match arg.as_str() {
+                        "-A" | "--after-context" => *after = lines,
+                        "-B" | "--before-context" => *before = lines,
+                        _ => (*before, *after) = (lines, lines),
+                    }
+                }
+
+                // Handle grep pattern and its hex bytes
+                "--hex" => {
+                    let g = grep.as_mut().ok_or("--hex is only valid with grep")?;
+                    let value = args.next().ok_or("--hex requires bytes")?;
+
+                    g.pattern = Self::parse_hex(&value)
+                        .filter(|p| !p.is_empty())
+                        .ok_or("invalid --hex bytes")?;
+                }
+
+                // Handle grep recursion flag
+                "-R" | "-r" | "--recursive" => {
+                    grep.as_mut()
+                        .ok_or("--recursive is only valid with grep")?
+                        .recursive = true;
+                }
+
+                // Handle grep file name filter and its pattern
+                "--glob" => {
+                    let g = grep.as_mut().ok_or("--glob is only valid with grep")?;
+
+                    g.glob = Some(args.next().ok_or("--glob requires pattern")?);
+                }
+
+                // Handle grep size filters and their byte counts
+                "--min-size" | "--max-size" => {
+                    let g = grep
+                        .as_mut()
+                        .ok_or("size filters are only valid with grep")?;
+                    let value = args.next().ok_or("size filters require bytes")?;
+                    let size = Self::parse_offset(&value).ok_or("invalid size filter bytes")?;
+
+                    match arg.as_str() {
+                        "--min-size" => g.min_size = Some(size),
+                        _ => g.max_size = Some(size),
+                    }
+                }
+
+                // Handle grep worker count
+                "-j" | "--jobs" => {
+                    let g = grep.as_mut().ok_or("--jobs is only valid with grep")?;
+
+                    g.jobs = args
+                        .next()
+                        .ok_or("--jobs requires count")?
+                        .parse()
+                        .map_err(|_| "invalid --jobs count")?;
+                }
+
+                // Every non flag of grep is a path to search
+                f if grep.is_some() => {
+                    if let Some(ref mut g) = grep {
+                        g.paths.push(PathBuf::from(f));
+                    }
+                }
+
                 // First non flag is the input file path
                 f => {
                     if input.as_os_str().is_empty() {
@@ -131,118 +541,667 @@ impl DumpX {
         }
 
         // Ensure at least one input file was provided
-        if input.as_os_str().is_empty() {
+        if grep.as_ref().is_some_and(|g| g.paths.is_empty())
+            || grep.is_none() && input.as_os_str().is_empty()
+        {
             return Err("missing input file");
         }
 
-        Ok(DumpX { input, output })
+        let modes = [
+            render.is_some(),
+            !replacements.is_empty(),
+            revert.is_some(),
+            fuzzy_hash,
+            reference.is_some(),
+            interactive,
+            grep.is_some(),
+        ];
+        if modes.into_iter().filter(|&m| m).count() > 1 {
+            return Err(
+                "grep, --render, --replace, --revert, --fuzzy-hash, --similarity and --interactive are mutually exclusive",
+            );
+        }
+
+        if map_base.is_some() && map.is_none() {
+            return Err("--map-base requires --map");
+        }
+
+        if arch.is_some() && !matches!(annotate, Some(annotate::Kind::Macho)) {
+            return Err("--arch requires --annotate macho");
+        }
+
+        if context.is_some() && !interactive && grep.is_none() {
+            return Err("-A, -B and -C require --interactive or grep");
+        }
+
+        if in_place && (replacements.is_empty() || output.is_some()) {
+            return Err("--in-place requires --replace and no --output");
+        }
+
+        let mode = if let Some(grep) = grep {
+            if grep.pattern.is_empty() {
+                return Err("grep requires --hex");
+            }
+
+            Mode::Grep(grep)
+        } else if let Some(path) = render {
+            if output.is_some() {
+                return Err("--render cannot be combined with --output");
+            }
+
+            Mode::Render {
+                path,
+                width,
+                palette,
+            }
+        } else if !replacements.is_empty() {
+            Mode::Replace {
+                patcher: Patcher {
+                    rules: replacements,
+                    max_count,
+                },
+                in_place,
+            }
+        } else if let Some(journal) = revert {
+            Mode::Revert(journal)
+        } else if fuzzy_hash {
+            Mode::FuzzyHash
+        } else if let Some(reference) = reference {
+            Mode::Similarity(reference)
+        } else if interactive {
+            if output.is_some() {
+                return Err("--interactive cannot be combined with --output");
+            }
+
+            Mode::Interactive
+        } else {
+            Mode::Dump
+        };
+
+        Ok(DumpX {
+            input,
+            output,
+            baseline,
+            color_rules,
+            rules: None,
+            map,
+            map_base: map_base.unwrap_or(0),
+            symbols: BTreeMap::new(),
+            seek,
+            annotate,
+            arch,
+            mappings: Vec::new(),
+            context,
+            labels: BTreeMap::new(),
+            mode,
+        })
     }
 
-    /// Opens the input file and dispatches to `dump`, handling output location.
-    fn run(self) -> io::Result<()> {
-        let file = File::open(&self.input)?;
+    /// Parses a single byte written in hex (`0xFF`) or decimal (`255`).
+    fn parse_byte(s: &str) -> Option<u8> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        }
+    }
 
-        if let Some(ref path) = self.output {
-            // Prevent overwriting existing files
-            if path.exists() {
-                return Err(io::Error::new(
-                    io::ErrorKind::AlreadyExists,
-                    format!("output file '{}' already exists", path.display()),
-                ));
+    /// Parses an offset written in hex (`0x1F0`) or decimal (`496`).
+    fn parse_offset(s: &str) -> Option<u64> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        }
+    }
+
+    /// Resolves an offset, a symbol, or either followed by `+OFFSET` or `-OFFSET`.
+    ///
+    /// A `va:ADDRESS` is translated to the offset it was loaded from.
+    fn resolve(&self, s: &str) -> Option<u64> {
+        if let Some(offset) = Self::parse_offset(s) {
+            return Some(offset);
+        }
+
+        if let Some(vaddr) = s.strip_prefix("va:") {
+            let vaddr = Self::parse_offset(vaddr)?;
+
+            return self
+                .mappings
+                .iter()
+                .find(|m| (m.vaddr..m.vaddr.saturating_add(m.len)).contains(&vaddr))
+                .and_then(|m| m.offset.checked_add(vaddr - m.vaddr));
+        }
+
+        // Split off a trailing displacement, symbols never contain `+` or `-`
+        let (base, delta) = match s.rfind(['+', '-']) {
+            Some(i) => (
+                &s[..i],
+                Some((&s[i..i + 1], Self::parse_offset(&s[i + 1..])?)),
+            ),
+            None => (s, None),
+        };
+
+        let base = Self::parse_offset(base).or_else(|| self.symbols.get(base).copied())?;
+
+        match delta {
+            Some(("+", d)) => base.checked_add(d),
+            Some((_, d)) => base.checked_sub(d),
+            None => Some(base),
+        }
+    }
+
+    /// Parses a hex byte string such as `cafebabe` or `CA FE BA BE`.
+    fn parse_hex(s: &str) -> Option<Vec<u8>> {
+        let digits = s
+            .bytes()
+            .filter(|b| !b.is_ascii_whitespace())
+            .map(|b| (b as char).to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<_>>>()?;
+
+        if digits.len() % 2 != 0 {
+            return None;
+        }
+
+        Some(digits.chunks(2).map(|p| (p[0] << 4) | p[1]).collect())
+    }
+
+    /// Formats `bytes` as a lowercase hex string without separators.
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .flat_map(|&b| Self::HEX_LUT[b as usize])
+            .map(char::from)
+            .collect()
+    }
+
+    /// Returns the escape sequence to style byte `b` with, if any.
+    ///
+    /// Coloring rules take precedence, the baseline styles what they leave alone.
+    fn style(&self, offset: u64, b: u8) -> Option<&[u8]> {
+        if let Some(style) = self.rules.as_ref().and_then(|r| r.style(offset, b)) {
+            return Some(style);
+        }
+
+        self.baseline.map(|base| {
+            if b == base {
+                Self::DIM
+            } else {
+                Self::HIGHLIGHT
+            }
+        })
+    }
+
+    /// Switches the styling in `line_buf` from `current` to `next` if they differ.
+    fn restyle<'a>(line_buf: &mut Vec<u8>, current: &mut Option<&'a [u8]>, next: Option<&'a [u8]>) {
+        if *current == next {
+            return;
+        }
+
+        match next {
+            Some(seq) => {
+                // Reset first so attributes from the previous style do not leak
+                if current.is_some() {
+                    line_buf.extend_from_slice(Self::RESET);
+                }
+                line_buf.extend_from_slice(seq);
+            }
+            None => line_buf.extend_from_slice(Self::RESET),
+        }
+
+        *current = next;
+    }
+
+    /// Opens the input file and dispatches on the mode, handling output location.
+    fn run(mut self) -> io::Result<()> {
+        // Grep has no single input, it opens its own files
+        if let Mode::Grep(ref grep) = self.mode {
+            let result = match self.output {
+                Some(ref path) => Self::create_new(path).and_then(|out| grep.run(&self, out)),
+                None => grep.run(&self, io::stdout().lock()),
+            };
+
+            // Exit as grep does, so scripts can tell no hits from failures
+            let status = result.unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                2
+            });
+            if status != 0 {
+                process::exit(status);
+            }
+
+            return Ok(());
+        }
+
+        let mut file = File::open(&self.input)?;
+
+        if let Some(ref path) = self.color_rules {
+            let mut rules = Rules::load(path)?;
+            rules.locate(&self.input)?;
+
+            self.rules = Some(rules);
+        }
+
+        if let Some(ref map) = self.map {
+            for (name, address) in symbols::load(map)? {
+                // Symbols below the load address are not part of the input
+                let Some(offset) = address.checked_sub(self.map_base) else {
+                    continue;
+                };
+
+                self.labels.entry(offset).or_default().push(name.clone());
+                self.symbols.insert(name, offset);
+            }
+        }
+
+        // Part of the file dumped, narrowed by a selected slice
+        let mut slice = (0, u64::MAX);
+
+        if let Some(kind) = self.annotate {
+            let annotations = annotate::annotate(kind, self.arch.as_deref(), &file)?;
+
+            for (offset, text) in annotations.labels {
+                self.labels.entry(offset).or_default().push(text);
+            }
+            self.mappings = annotations.mappings;
+            slice = annotations.slice.unwrap_or(slice);
+        }
+
+        let start = match self.seek {
+            Some(ref target) => {
+                let start = self.resolve(target).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown --seek offset or symbol '{}'", target),
+                    )
+                })?;
+
+                // Seeking past the end would dump nothing and look like success
+                if start >= file.metadata()?.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "--seek target '{}' at 0x{:016x} is past the end of the input",
+                            target, start
+                        ),
+                    ));
+                }
+
+                start
+            }
+            None => slice.0,
+        };
+
+        if let Mode::Render {
+            ref path,
+            width,
+            palette,
+        } = self.mode
+        {
+            let len = file.metadata()?.len();
+
+            return png::render(
+                io::BufReader::new(file),
+                len,
+                width,
+                palette,
+                io::BufWriter::new(Self::create_new(path)?),
+            );
+        }
+
+        if let Mode::Replace {
+            ref patcher,
+            in_place,
+        } = self.mode
+        {
+            return match self.output {
+                _ if in_place => self.replace_in_place(patcher, file),
+                Some(ref path) => self.replace(patcher, file, Self::create_new(path)?),
+                None => self.replace(patcher, file, io::stdout().lock()),
+            };
+        }
+
+        if let Mode::Revert(ref journal) = self.mode {
+            let count = Journal::revert(journal, &self.input)?;
+            eprintln!("{} edit(s) reverted", count);
+
+            return Ok(());
+        }
+
+        if let Mode::FuzzyHash = self.mode {
+            let len = file.metadata()?.len();
+            let hash = fuzzy::hash(io::BufReader::new(file), len)?;
+
+            // Same line format as ssdeep, so signatures can be compared with it
+            let line = format!("{},\"{}\"\n", hash, self.input.display());
+
+            return match self.output {
+                Some(ref path) => Self::create_new(path)?.write_all(line.as_bytes()),
+                None => io::stdout().lock().write_all(line.as_bytes()),
+            };
+        }
+
+        if let Mode::Similarity(ref reference) = self.mode {
+            return match self.output {
+                Some(ref path) => self.similarity(reference, file, Self::create_new(path)?),
+                None => self.similarity(reference, file, io::stdout().lock()),
+            };
+        }
+
+        if let Mode::Interactive = self.mode {
+            let seeked = self.seek.is_some() || start != 0;
+            let mut repl = Repl::new(&mut self, file)?;
+
+            // An explicit --seek wins over the offset restored from the session
+            if seeked {
+                repl.goto(start);
             }
 
+            return repl.run(io::stdin().lock(), io::stdout().lock());
+        }
+
+        file.seek(SeekFrom::Start(start))?;
+        let input = file.take(slice.1.saturating_sub(start));
+
+        if let Some(ref path) = self.output {
             // Create a new output file and perform the dump
-            self.dump(file, File::create(path)?)?;
+            self.dump(input, start, Self::create_new(path)?)?;
         } else {
             // No output file: write to stdout
-            self.dump(file, io::stdout().lock())?;
+            self.dump(input, start, io::stdout().lock())?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the path of a file living next to `path`, named with `suffix` appended.
+    fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+
+        PathBuf::from(name)
+    }
+
+    /// Creates a new output file at `path`, refusing to overwrite an existing one.
+    fn create_new(path: &PathBuf) -> io::Result<File> {
+        // Prevent overwriting existing files
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("output file '{}' already exists", path.display()),
+            ));
+        }
+
+        File::create(path)
+    }
+
+    /// Copies the input file to `out` with substitutions, reporting each on stderr.
+    fn replace<W: Write>(&self, patcher: &Patcher, file: File, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+        let mut err = io::stderr().lock();
+
+        let count = patcher.run(
+            file,
+            |data| out.write_all(data),
+            |offset, find, replace| {
+                writeln!(
+                    err,
+                    "0x{:016x}: {} -> {}",
+                    offset,
+                    Self::to_hex(find),
+                    Self::to_hex(replace)
+                )
+            },
+        )?;
+
+        writeln!(err, "{} replacement(s)", count)?;
+
+        out.flush()
+    }
+
+    /// Substitutes bytes directly in the input file, journaling each edit first.
+    fn replace_in_place(&self, patcher: &Patcher, file: File) -> io::Result<()> {
+        let journal_path = Journal::path_for(&self.input);
+
+        let mut target = OpenOptions::new().write(true).open(&self.input)?;
+        let mut journal = None;
+        let mut err = io::stderr().lock();
+
+        let count = patcher.run(
+            io::BufReader::new(file),
+            |_| Ok(()),
+            |offset, find, replace| {
+                writeln!(
+                    err,
+                    "0x{:016x}: {} -> {}",
+                    offset,
+                    Self::to_hex(find),
+                    Self::to_hex(replace)
+                )?;
+
+                // Only create the journal once there is something to record
+                if journal.is_none() {
+                    journal = Some(Journal::open(&journal_path)?);
+                }
+                if let Some(ref mut journal) = journal {
+                    journal.record(offset, find, replace)?;
+                }
+
+                target.seek(SeekFrom::Start(offset))?;
+                target.write_all(replace)
+            },
+        )?;
+
+        writeln!(err, "{} replacement(s)", count)?;
+        if count > 0 {
+            writeln!(err, "journal: {}", journal_path.display())?;
+        }
+
+        target.sync_data()
+    }
+
+    /// Writes a summary of the input's regions shared with `reference` to `out`.
+    fn similarity<W: Write>(&self, reference: &PathBuf, mut file: File, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+
+        // Matching needs random access to both sides, so load them whole
+        let theirs = fs::read(reference)?;
+        let mut ours = Vec::new();
+        file.read_to_end(&mut ours)?;
+
+        let regions = similarity::compare(&theirs, &ours);
+        let (shared, unique): (Vec<_>, Vec<_>) = regions.iter().partition(|r| r.source.is_some());
+        let shared_len: usize = shared.iter().map(|r| r.len).sum();
+
+        let percent = if ours.is_empty() {
+            0.0
+        } else {
+            shared_len as f64 * 100.0 / ours.len() as f64
+        };
+
+        writeln!(
+            out,
+            "similarity: {:.2}% ({} of {} bytes of '{}' found in '{}')",
+            percent,
+            shared_len,
+            ours.len(),
+            self.input.display(),
+            reference.display()
+        )?;
+        writeln!(
+            out,
+            "shared: {} bytes in {} region(s), unique: {} bytes in {} region(s)",
+            shared_len,
+            shared.len(),
+            ours.len() - shared_len,
+            unique.len()
+        )?;
+
+        for r in &regions {
+            write!(
+                out,
+                "0x{:016x}..0x{:016x}  {:>10} bytes  ",
+                r.start,
+                r.start + r.len,
+                r.len
+            )?;
+
+            match r.source {
+                Some(source) => writeln!(out, "shared  (reference 0x{:016x})", source)?,
+                None => writeln!(out, "unique")?,
+            }
+        }
+
+        out.flush()
+    }
+
+    /// Dumps the lines holding each `len` byte match at `matches`, with context lines.
+    ///
+    /// Groups whose context overlaps or touches are merged, and `--` separates the rest.
+    fn dump_matches<R: Read + Seek, W: Write>(
+        &self,
+        mut input: R,
+        matches: &[u64],
+        len: u64,
+        mut out: W,
+    ) -> io::Result<()> {
+        let (before, after) = self.context.unwrap_or((0, 0));
+        let width = Self::WIDTH as u64;
+
+        // Inclusive line ranges to dump, in order
+        let mut groups: Vec<(u64, u64)> = Vec::new();
+        for &m in matches {
+            let first = (m / width).saturating_sub(before);
+            let last = ((m + len.max(1) - 1) / width).saturating_add(after);
+
+            match groups.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+                _ => groups.push((first, last)),
+            }
+        }
+
+        for (i, &(first, last)) in groups.iter().enumerate() {
+            if i > 0 {
+                writeln!(out, "--")?;
+            }
+
+            // Huge context saturates, the dump stops at the end of the file anyway
+            let lines = (last - first).saturating_add(1);
+
+            input.seek(SeekFrom::Start(first * width))?;
+            self.dump(
+                (&mut input).take(lines.saturating_mul(width)),
+                first * width,
+                &mut out,
+            )?;
         }
 
         Ok(())
     }
 
-    /// Reads the input file in chunks and writes formatted lines to `out`.
-    fn dump<W: Write>(&self, mut file: File, mut out: W) -> io::Result<()> {
+    /// Reads `input` in chunks and writes formatted lines to `out`.
+    ///
+    /// Offsets are printed relative to `start`, the position of `input` within the file.
+    fn dump<R: Read, W: Write>(&self, mut input: R, start: u64, mut out: W) -> io::Result<()> {
         let mut io_buf = [0u8; Self::IO_BUF_SIZE];
 
-        let mut line_offset = 0usize;
-        let mut line_buf = [0u8; Self::LINE_BUF_SIZE];
+        let mut line_offset = start;
+        let mut line_buf = Vec::with_capacity(Self::LINE_BUF_SIZE);
+
+        // Tell which mapping a dump starting part way into one belongs to
+        if let Some(m) = self
+            .mappings
+            .iter()
+            .find(|m| start > m.offset && start - m.offset < m.len)
+        {
+            writeln!(
+                out,
+                "; 0x{:016x}: within {} at vaddr 0x{:x}",
+                start,
+                m.name,
+                m.vaddr.saturating_add(start - m.offset)
+            )?;
+        }
 
         // Read the file until EOF
-        while let Ok(n) = file.read(&mut io_buf) {
+        while let Ok(n) = input.read(&mut io_buf) {
             if n == 0 {
                 break;
             }
 
             // Process each WIDTH sized chunk from the buffer
             for chunk in io_buf[..n].chunks(Self::WIDTH) {
-                let mut i = 0;
+                line_buf.clear();
+
+                // Label section: one line per label falling within this line
+
+                let end = line_offset + chunk.len() as u64;
+                for (offset, texts) in self.labels.range(line_offset..end) {
+                    for text in texts {
+                        writeln!(out, "; 0x{:016x}: {}", offset, text)?;
+                    }
+                }
 
                 // Prefix section: Write the offset prefix
 
-                line_buf[i..i + 2].copy_from_slice(b"0x");
-                i += 2;
+                line_buf.extend_from_slice(b"0x");
 
                 for shift in (0..16).rev() {
-                    line_buf[i] = Self::NIBBLE_LUT[(line_offset >> (shift * 4)) & 0xF];
-                    i += 1;
+                    line_buf.push(Self::NIBBLE_LUT[((line_offset >> (shift * 4)) & 0xF) as usize]);
                 }
 
-                line_buf[i..i + 2].copy_from_slice(b": ");
-                i += 2;
+                line_buf.extend_from_slice(b": ");
 
                 // Hex section: group bytes and insert spaces
 
                 let mut hex_written = 0;
+                let mut current = None;
                 for (j, &b) in chunk.iter().enumerate() {
+                    let style = self.style(line_offset + j as u64, b);
+                    Self::restyle(&mut line_buf, &mut current, style);
+
                     if j > 0 {
                         if j % Self::GROUP_SIZE == 0 {
-                            line_buf[i..i + 2].copy_from_slice(b"  ");
-                            i += 2;
+                            line_buf.extend_from_slice(b"  ");
                             hex_written += 2;
                         } else {
-                            line_buf[i] = b' ';
-                            i += 1;
+                            line_buf.push(b' ');
                             hex_written += 1;
                         }
                     }
 
                     // Copy the 2 char hex for this byte
-                    line_buf[i..i + 2].copy_from_slice(&Self::HEX_LUT[b as usize]);
-                    i += 2;
+                    line_buf.extend_from_slice(&Self::HEX_LUT[b as usize]);
                     hex_written += 2;
                 }
+                Self::restyle(&mut line_buf, &mut current, None);
 
                 // Pad any remaining space in the hex section
-                for _ in 0..(Self::HEX_SECTION - hex_written) {
-                    line_buf[i] = b' ';
-                    i += 1;
-                }
+                line_buf.resize(line_buf.len() + (Self::HEX_SECTION - hex_written), b' ');
 
                 // Separator between hex and ASCII sections
-                line_buf[i..i + 2].copy_from_slice(b"  ");
-                i += 2;
+                line_buf.extend_from_slice(b"  ");
 
                 // ASCII section: printable bytes or placeholder
 
-                for &b in chunk.iter() {
-                    line_buf[i] = if (0x20..=0x7E).contains(&b) {
+                for (j, &b) in chunk.iter().enumerate() {
+                    let style = self.style(line_offset + j as u64, b);
+                    Self::restyle(&mut line_buf, &mut current, style);
+
+                    line_buf.push(if (0x20..=0x7E).contains(&b) {
                         b
                     } else {
                         Self::NON_ASCII
-                    };
-                    i += 1;
+                    });
                 }
+                Self::restyle(&mut line_buf, &mut current, None);
 
                 // Add newline
-                line_buf[i] = b'\n';
-                i += 1;
+                line_buf.push(b'\n');
 
                 // Write the completed line to output
-                out.write_all(&line_buf[..i])?;
+                out.write_all(&line_buf)?;
 
                 // Update the offset for the next line
-                line_offset += chunk.len();
+                line_offset += chunk.len() as u64;
             }
         }
 
@@ -0,0 +1,197 @@
+//! Streaming byte search and replace backing `--replace`.
+
+use std::io::{self, Read};
+
+/// Equal length find/replace pairs applied to a stream from left to right.
+pub struct Patcher {
+    /// Bytes to find and their replacement, earlier pairs win on ties.
+    pub rules: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// Maximum number of replacements to make. If `None`, replaces all.
+    pub max_count: Option<usize>,
+}
+
+impl Patcher {
+    /// I/O buffer size for reading chunks from the input.
+    const IO_BUF_SIZE: usize = 64 * 1024;
+
+    /// Streams `input` through the rules, returning the number of replacements.
+    ///
+    /// Patched data is handed to `sink` in order. Each replacement is reported
+    /// to `on_patch` with its offset, old and new bytes before the data holding
+    /// it reaches `sink`. Replaced bytes are never matched again.
+    pub fn run<R: Read>(
+        &self,
+        mut input: R,
+        mut sink: impl FnMut(&[u8]) -> io::Result<()>,
+        mut on_patch: impl FnMut(u64, &[u8], &[u8]) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        let longest = self.rules.iter().map(|(f, _)| f.len()).max().unwrap_or(1);
+
+        let mut io_buf = vec![0u8; Self::IO_BUF_SIZE];
+        let mut buf = Vec::with_capacity(Self::IO_BUF_SIZE + longest);
+
+        // Offset of `buf[0]` within the input
+        let mut base = 0u64;
+        let mut count = 0;
+
+        loop {
+            let n = input.read(&mut io_buf)?;
+            buf.extend_from_slice(&io_buf[..n]);
+
+            // Until EOF, keep back a tail the longest pattern could still match into
+            let limit = if n == 0 {
+                buf.len()
+            } else {
+                buf.len().saturating_sub(longest - 1)
+            };
+
+            let mut i = 0;
+            while i < limit {
+                if self.max_count.is_some_and(|max| count >= max) {
+                    i = limit;
+                    break;
+                }
+
+                match self.rules.iter().find(|(f, _)| buf[i..].starts_with(f)) {
+                    Some((find, replace)) => {
+                        on_patch(base + i as u64, find, replace)?;
+
+                        buf[i..i + find.len()].copy_from_slice(replace);
+                        i += find.len();
+                        count += 1;
+                    }
+                    None => i += 1,
+                }
+            }
+
+            sink(&buf[..i])?;
+            buf.drain(..i);
+            base += i as u64;
+
+            if n == 0 {
+                break;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Read};
+
+    use super::Patcher;
+
+    /// Reader handing out at most `step` bytes per read.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+
+            Ok(n)
+        }
+    }
+
+    fn patcher(rules: &[(&[u8], &[u8])], max_count: Option<usize>) -> Patcher {
+        Patcher {
+            rules: rules
+                .iter()
+                .map(|&(f, r)| (f.to_vec(), r.to_vec()))
+                .collect(),
+            max_count,
+        }
+    }
+
+    /// Runs `p` over `input`, returning the output, patch offsets and count.
+    fn patch(p: &Patcher, input: impl Read) -> (Vec<u8>, Vec<u64>, usize) {
+        let (mut out, mut offsets) = (Vec::new(), Vec::new());
+
+        let count = p
+            .run(
+                input,
+                |data| {
+                    out.extend_from_slice(data);
+                    Ok(())
+                },
+                |offset, _, _| {
+                    offsets.push(offset);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        (out, offsets, count)
+    }
+
+    #[test]
+    fn match_across_read_boundary() {
+        let p = patcher(&[(b"\xca\xfe\xba\xbe", b"\xde\xad\xbe\xef")], None);
+
+        // Straddle the end of the first full buffer
+        let at = Patcher::IO_BUF_SIZE - 2;
+        let mut input = vec![0u8; 2 * Patcher::IO_BUF_SIZE];
+        input[at..at + 4].copy_from_slice(b"\xca\xfe\xba\xbe");
+
+        let mut expected = input.clone();
+        expected[at..at + 4].copy_from_slice(b"\xde\xad\xbe\xef");
+
+        assert_eq!(
+            patch(&p, Cursor::new(&input)),
+            (expected.clone(), vec![at as u64], 1)
+        );
+        assert_eq!(
+            patch(
+                &p,
+                Trickle {
+                    data: &input,
+                    step: 3
+                }
+            ),
+            (expected, vec![at as u64], 1)
+        );
+    }
+
+    #[test]
+    fn match_at_end_of_input() {
+        let p = patcher(&[(b"world", b"WORLD")], None);
+
+        assert_eq!(
+            patch(
+                &p,
+                Trickle {
+                    data: b"hello world",
+                    step: 1
+                }
+            ),
+            (b"hello WORLD".to_vec(), vec![6], 1)
+        );
+    }
+
+    #[test]
+    fn replaced_bytes_are_not_matched_again() {
+        let p = patcher(&[(b"aa", b"ba"), (b"ab", b"xx")], None);
+
+        assert_eq!(
+            patch(&p, Cursor::new(b"aaab")),
+            (b"baxx".to_vec(), vec![0, 2], 2)
+        );
+    }
+
+    #[test]
+    fn stops_at_max_count() {
+        let p = patcher(&[(b"a", b"b")], Some(2));
+
+        assert_eq!(
+            patch(&p, Cursor::new(b"aaaa")),
+            (b"bbaa".to_vec(), vec![0, 1], 2)
+        );
+    }
+}
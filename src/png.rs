@@ -0,0 +1,236 @@
+//! Minimal PNG encoder backing `--render`.
+//!
+//! Every byte of the input becomes one pixel, either as a gray level or as an
+//! index into a byte class palette. Image data is written as uncompressed
+//! deflate blocks, which keeps the encoder dependency free and streaming.
+
+use std::io::{self, Read, Write};
+
+/// How byte values are mapped to pixel colors.
+#[derive(Clone, Copy)]
+pub enum Palette {
+    /// The byte value is used directly as the gray level.
+    Gray,
+
+    /// Bytes are colored by class: zero, control, printable ASCII, high, 0xFF.
+    Class,
+}
+
+impl Palette {
+    /// Parses a palette name as given on the command line.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "gray" | "grey" => Some(Palette::Gray),
+            "class" => Some(Palette::Class),
+            _ => None,
+        }
+    }
+
+    /// RGB color for each byte value when rendering by class.
+    const CLASS_COLORS: [[u8; 3]; 256] = {
+        let mut m = [[0u8; 3]; 256];
+        let mut i = 0;
+
+        while i < 256 {
+            m[i] = match i {
+                0x00 => [0, 0, 0],
+                0xFF => [255, 255, 255],
+                0x20..=0x7E => [55, 126, 184],
+                0x01..=0x1F | 0x7F => [77, 175, 74],
+                _ => [228, 26, 28],
+            };
+            i += 1;
+        }
+        m
+    };
+}
+
+/// PNG file signature.
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Largest payload of a single stored deflate block.
+const MAX_STORED: usize = 0xFFFF;
+
+/// Largest width or height allowed by the PNG specification.
+const MAX_DIMENSION: u64 = (1 << 31) - 1;
+
+/// Precomputed CRC-32 table (reflected polynomial `0xEDB88320`).
+const CRC_LUT: [u32; 256] = {
+    let mut m = [0u32; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        m[i] = c;
+        i += 1;
+    }
+    m
+};
+
+/// Renders `len` bytes read from `input` as a PNG image `width` pixels wide.
+///
+/// The last row is padded with zero bytes when `len` is not a multiple of `width`.
+pub fn render<R: Read, W: Write>(
+    mut input: R,
+    len: u64,
+    width: u32,
+    palette: Palette,
+    mut out: W,
+) -> io::Result<()> {
+    if len == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot render an empty file",
+        ));
+    }
+
+    let height = len.div_ceil(width as u64);
+    if width as u64 > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "image dimensions exceed the PNG limits",
+        ));
+    }
+
+    out.write_all(&SIGNATURE)?;
+
+    // Header: dimensions, bit depth 8, gray (0) or indexed (3) color type
+    let mut ihdr = [0u8; 13];
+    ihdr[0..4].copy_from_slice(&width.to_be_bytes());
+    ihdr[4..8].copy_from_slice(&(height as u32).to_be_bytes());
+    ihdr[8] = 8;
+    ihdr[9] = match palette {
+        Palette::Gray => 0,
+        Palette::Class => 3,
+    };
+    chunk(&mut out, b"IHDR", &[&ihdr])?;
+
+    if let Palette::Class = palette {
+        chunk(&mut out, b"PLTE", &[Palette::CLASS_COLORS.as_flattened()])?;
+    }
+
+    // zlib header: deflate with a 32K window, no preset dictionary
+    chunk(&mut out, b"IDAT", &[&[0x78, 0x01]])?;
+
+    let mut idat = Idat {
+        out: &mut out,
+        block: Vec::with_capacity(MAX_STORED),
+        adler: (1, 0),
+    };
+
+    let mut row = vec![0u8; width as usize];
+    for _ in 0..height {
+        // Fill the row, leaving zero padding once the input runs dry
+        let mut filled = 0;
+        while filled < row.len() {
+            match input.read(&mut row[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        row[filled..].fill(0);
+
+        // Each row starts with its filter type, always "None" here
+        idat.write(&[0])?;
+        idat.write(&row)?;
+    }
+
+    idat.finish()?;
+    chunk(&mut out, b"IEND", &[])?;
+
+    out.flush()
+}
+
+/// Streams image data into IDAT chunks, one stored deflate block per chunk.
+struct Idat<'a, W: Write> {
+    /// Destination of the chunks.
+    out: &'a mut W,
+
+    /// Pending uncompressed data for the next block.
+    block: Vec<u8>,
+
+    /// Running Adler-32 state `(a, b)` of all data written.
+    adler: (u32, u32),
+}
+
+impl<W: Write> Idat<'_, W> {
+    /// Adler-32 modulus.
+    const ADLER_MOD: u32 = 65521;
+
+    /// Appends `data`, emitting full blocks as they fill up.
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        for &b in data {
+            self.adler.0 = (self.adler.0 + b as u32) % Self::ADLER_MOD;
+            self.adler.1 = (self.adler.1 + self.adler.0) % Self::ADLER_MOD;
+        }
+
+        while !data.is_empty() {
+            let n = data.len().min(MAX_STORED - self.block.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+
+            if self.block.len() == MAX_STORED {
+                self.flush_block()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits the pending data as a non-final stored block.
+    fn flush_block(&mut self) -> io::Result<()> {
+        let len = self.block.len() as u16;
+
+        let mut head = [0u8; 5];
+        head[1..3].copy_from_slice(&len.to_le_bytes());
+        head[3..5].copy_from_slice(&(!len).to_le_bytes());
+
+        chunk(self.out, b"IDAT", &[&head, &self.block])?;
+        self.block.clear();
+
+        Ok(())
+    }
+
+    /// Flushes pending data and terminates the zlib stream.
+    fn finish(mut self) -> io::Result<()> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+
+        // Empty final stored block followed by the Adler-32 checksum
+        let adler = (self.adler.1 << 16) | self.adler.0;
+        chunk(
+            self.out,
+            b"IDAT",
+            &[&[0x01, 0x00, 0x00, 0xFF, 0xFF], &adler.to_be_bytes()],
+        )
+    }
+}
+
+/// Writes a PNG chunk of type `kind` whose data is the concatenation of `parts`.
+fn chunk<W: Write>(out: &mut W, kind: &[u8; 4], parts: &[&[u8]]) -> io::Result<()> {
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    out.write_all(&(len as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+
+    let mut crc = !0u32;
+    for &b in kind.iter().chain(parts.iter().flat_map(|p| p.iter())) {
+        crc = CRC_LUT[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    for part in parts {
+        out.write_all(part)?;
+    }
+
+    out.write_all(&(!crc).to_be_bytes())
+}
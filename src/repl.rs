@@ -0,0 +1,402 @@
+//! Line based interactive mode backing `--interactive`.
+//!
+//! Reads one command per line and answers on the output, without taking over
+//! the terminal, so it works over slow links and when driven by scripts. The
+//! session state is restored on start and saved on exit.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{DumpX, journal::Journal, search, session::Session};
+
+/// Interactive session over a single input file.
+pub struct Repl<'a> {
+    /// Options the session was started with, updated by `s`.
+    dumpx: &'a mut DumpX,
+
+    /// Read handle of the input file.
+    file: File,
+
+    /// Persistent state, its offset is where a dump without one continues.
+    session: Session,
+
+    /// Whether `session` was restored from a previous run.
+    restored: bool,
+}
+
+impl<'a> Repl<'a> {
+    /// Prompt printed before reading each command.
+    const PROMPT: &'static str = "dumpx> ";
+
+    /// Number of bytes dumped when no length is given.
+    const DUMP_LEN: u64 = 256;
+
+    /// Command summary printed by `h`.
+    const HELP: &'static str = concat!(
+        "Commands:\n",
+        "  d [OFFSET] [LEN]    Dump LEN bytes at OFFSET (default: continue, 256 bytes)\n",
+        "  f <HEX>             Find every occurrence of the hex bytes, dumping context if set\n",
+        "  i [OFFSET]          Inspect the integers and floats stored at OFFSET\n",
+        "  w <OFFSET> <HEX>    Write the hex bytes at OFFSET, journaling the edit\n",
+        "  b [NAME] [OFFSET]   Bookmark OFFSET (default: current) as NAME, or list bookmarks\n",
+        "  l [OFFSET] [TEXT]   Label OFFSET with TEXT, or list labels\n",
+        "  u <NAME|OFFSET>     Remove a bookmark or the label at OFFSET\n",
+        "  s [OPTION] [VALUE]  Set baseline <BYTE|off> or length <LEN>, or list options\n",
+        "  h                   Show this help\n",
+        "  q                   Quit, saving the session\n",
+        "Bookmark and symbol names can be used wherever an OFFSET is expected.\n",
+        "An empty line continues the last dump.\n",
+    );
+
+    /// Starts a session over `file`, the already opened input of `dumpx`.
+    ///
+    /// Restores the previous session of the same file, if any. Options given on
+    /// the command line take precedence over restored ones.
+    pub fn new(dumpx: &'a mut DumpX, file: File) -> io::Result<Self> {
+        let (mut session, restored) = Session::load(&dumpx.input, Self::DUMP_LEN)?;
+
+        match dumpx.baseline {
+            Some(b) => session.baseline = Some(b),
+            None => dumpx.baseline = session.baseline,
+        }
+
+        for (&offset, text) in &session.labels {
+            dumpx.labels.entry(offset).or_default().push(text.clone());
+        }
+
+        Ok(Repl {
+            dumpx,
+            file,
+            session,
+            restored,
+        })
+    }
+
+    /// Moves the offset where the next dump continues.
+    pub fn goto(&mut self, offset: u64) {
+        self.session.offset = offset;
+    }
+
+    /// Reads commands from `input` until `q` or EOF, answering on `out`.
+    ///
+    /// The session is saved on the way out, even when reading fails.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        if self.restored {
+            writeln!(
+                out,
+                "restored session from '{}' at 0x{:016x}",
+                self.session.path.display(),
+                self.session.offset
+            )?;
+        }
+
+        let result = self.prompt(input, &mut out);
+        let saved = self.session.save();
+
+        result.and(saved)
+    }
+
+    /// Prompts for and executes commands until `q` or EOF.
+    fn prompt<R: BufRead, W: Write>(&mut self, mut input: R, out: &mut W) -> io::Result<()> {
+        let mut line = String::new();
+
+        loop {
+            write!(out, "{}", Self::PROMPT)?;
+            out.flush()?;
+
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                // Leave the terminal on a fresh line after EOF
+                writeln!(out)?;
+                return Ok(());
+            }
+
+            match self.command(line.trim(), out) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => writeln!(out, "Error: {}", e)?,
+            }
+        }
+    }
+
+    /// Executes one command line. Returns `false` once the session should end.
+    fn command<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let (cmd, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        match cmd {
+            "" => self.dump(self.session.offset, self.session.length, out)?,
+
+            "d" => {
+                let offset = match args.first() {
+                    Some(s) => self.offset(s)?,
+                    None => self.session.offset,
+                };
+                let len = match args.get(1) {
+                    Some(s) => Self::length(s)?,
+                    None => self.session.length,
+                };
+
+                self.dump(offset, len, out)?;
+            }
+
+            "f" => {
+                let needle = DumpX::parse_hex(rest)
+                    .filter(|n| !n.is_empty())
+                    .ok_or_else(|| invalid("f requires hex bytes"))?;
+
+                self.file.seek(SeekFrom::Start(0))?;
+                let matches = search::find(io::BufReader::new(&self.file), &needle)?;
+
+                if self.dumpx.context.is_some() {
+                    self.dumpx.dump_matches(
+                        &self.file,
+                        &matches,
+                        needle.len() as u64,
+                        &mut *out,
+                    )?;
+                } else {
+                    for offset in &matches {
+                        writeln!(out, "0x{:016x}", offset)?;
+                    }
+                }
+                writeln!(out, "{} match(es)", matches.len())?;
+            }
+
+            "i" => {
+                let offset = match args.first() {
+                    Some(s) => self.offset(s)?,
+                    None => self.session.offset,
+                };
+
+                self.inspect(offset, out)?;
+            }
+
+            "w" => {
+                let (offset, bytes) = rest
+                    .trim_start()
+                    .split_once(' ')
+                    .ok_or_else(|| invalid("w requires offset and hex bytes"))?;
+
+                let offset = self.offset(offset)?;
+                let bytes = DumpX::parse_hex(bytes)
+                    .filter(|b| !b.is_empty())
+                    .ok_or_else(|| invalid("w requires hex bytes"))?;
+
+                self.write(offset, &bytes, out)?;
+            }
+
+            "b" => match args[..] {
+                [] => {
+                    for (name, offset) in &self.session.bookmarks {
+                        writeln!(out, "0x{:016x}  {}", offset, name)?;
+                    }
+                }
+                [name] | [name, _] => {
+                    // Names must not be mistaken for offsets when resolved
+                    if DumpX::parse_offset(name).is_some() {
+                        return Err(invalid("bookmark names cannot be numbers"));
+                    }
+
+                    let offset = match args.get(1) {
+                        Some(s) => self.offset(s)?,
+                        None => self.session.offset,
+                    };
+                    self.session.bookmarks.insert(name.to_owned(), offset);
+                }
+                _ => return Err(invalid("b takes a name and an optional offset")),
+            },
+
+            "l" => match rest.trim().split_once(' ') {
+                _ if args.is_empty() => {
+                    for (offset, text) in &self.session.labels {
+                        writeln!(out, "0x{:016x}  {}", offset, text)?;
+                    }
+                }
+                Some((offset, text)) => {
+                    let offset = self.offset(offset)?;
+                    let text = text.trim().to_owned();
+
+                    self.unlabel(offset);
+                    self.dumpx
+                        .labels
+                        .entry(offset)
+                        .or_default()
+                        .push(text.clone());
+                    self.session.labels.insert(offset, text);
+                }
+                None => return Err(invalid("l requires offset and text")),
+            },
+
+            "u" => match args[..] {
+                [target] => {
+                    let removed = self.session.bookmarks.remove(target).is_some()
+                        || DumpX::parse_offset(target).is_some_and(|o| self.unlabel(o));
+
+                    if !removed {
+                        return Err(invalid("no such bookmark or label"));
+                    }
+                }
+                _ => return Err(invalid("u requires a bookmark name or offset")),
+            },
+
+            "s" => match args[..] {
+                [] => {
+                    match self.session.baseline {
+                        Some(b) => writeln!(out, "baseline 0x{:02x}", b)?,
+                        None => writeln!(out, "baseline off")?,
+                    }
+                    writeln!(out, "length {}", self.session.length)?;
+                }
+                ["baseline", "off"] => self.set_baseline(None),
+                ["baseline", value] => {
+                    let b = DumpX::parse_byte(value).ok_or_else(|| invalid("invalid byte"))?;
+                    self.set_baseline(Some(b));
+                }
+                ["length", value] => self.session.length = Self::length(value)?,
+                _ => return Err(invalid("s takes baseline <BYTE|off> or length <LEN>")),
+            },
+
+            "h" | "help" | "?" => write!(out, "{}", Self::HELP)?,
+
+            "q" | "quit" | "exit" => return Ok(false),
+
+            _ => return Err(invalid("unknown command, try h")),
+        }
+
+        Ok(true)
+    }
+
+    /// Dumps `len` bytes at `offset` and moves the cursor past them.
+    fn dump<W: Write>(&mut self, offset: u64, len: u64, out: &mut W) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.dumpx.dump((&self.file).take(len), offset, &mut *out)?;
+
+        self.session.offset = offset.saturating_add(len);
+
+        Ok(())
+    }
+
+    /// Prints the values of every primitive type stored at `offset`.
+    fn inspect<W: Write>(&mut self, offset: u64, out: &mut W) -> io::Result<()> {
+        let mut bytes = [0u8; 8];
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut n = 0;
+        while n < bytes.len() {
+            match self.file.read(&mut bytes[n..])? {
+                0 => break,
+                read => n += read,
+            }
+        }
+
+        if n == 0 {
+            return Err(invalid("offset is past the end of the file"));
+        }
+
+        writeln!(out, "0x{:016x}", offset)?;
+        writeln!(
+            out,
+            "  {:<5} {:>24} {:>24}",
+            "type", "little endian", "big endian"
+        )?;
+
+        // One row per type that still fits before the end of the file. Debug
+        // formatting keeps extreme floats in scientific notation.
+        macro_rules! row {
+            ($($t:ty),*) => {$(
+                if let Ok(b) = bytes[..n.min(size_of::<$t>())].try_into() {
+                    writeln!(
+                        out,
+                        "  {:<5} {:>24?} {:>24?}",
+                        stringify!($t),
+                        <$t>::from_le_bytes(b),
+                        <$t>::from_be_bytes(b)
+                    )?;
+                }
+            )*};
+        }
+        row!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+        Ok(())
+    }
+
+    /// Overwrites the file with `bytes` at `offset`, journaling the old bytes first.
+    fn write<W: Write>(&mut self, offset: u64, bytes: &[u8], out: &mut W) -> io::Result<()> {
+        let mut old = vec![0u8; bytes.len()];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file
+            .read_exact(&mut old)
+            .map_err(|_| invalid("write would extend past the end of the file"))?;
+
+        // Open the target first, a journal entry for an edit that cannot happen would block reverts
+        let mut target = OpenOptions::new().write(true).open(&self.dumpx.input)?;
+
+        let journal_path = Journal::path_for(&self.dumpx.input);
+        Journal::open(&journal_path)?.record(offset, &old, bytes)?;
+
+        target.seek(SeekFrom::Start(offset))?;
+        target.write_all(bytes)?;
+        target.sync_data()?;
+
+        // The edit may create or break occurrences of pattern rules
+        if let Some(ref mut rules) = self.dumpx.rules {
+            rules.locate(&self.dumpx.input)?;
+        }
+
+        writeln!(
+            out,
+            "0x{:016x}: {} -> {} (journal: {})",
+            offset,
+            DumpX::to_hex(&old),
+            DumpX::to_hex(bytes),
+            journal_path.display()
+        )
+    }
+
+    /// Removes the session label at `offset`, returning whether there was one.
+    fn unlabel(&mut self, offset: u64) -> bool {
+        let Some(text) = self.session.labels.remove(&offset) else {
+            return false;
+        };
+
+        if let Some(texts) = self.dumpx.labels.get_mut(&offset) {
+            if let Some(i) = texts.iter().position(|t| *t == text) {
+                texts.remove(i);
+            }
+            if texts.is_empty() {
+                self.dumpx.labels.remove(&offset);
+            }
+        }
+
+        true
+    }
+
+    /// Changes the baseline of both the view and the saved session.
+    fn set_baseline(&mut self, baseline: Option<u8>) {
+        self.dumpx.baseline = baseline;
+        self.session.baseline = baseline;
+    }
+
+    /// Resolves an offset argument: a number, bookmark name or symbol.
+    fn offset(&self, s: &str) -> io::Result<u64> {
+        self.session
+            .bookmarks
+            .get(s)
+            .copied()
+            .or_else(|| self.dumpx.resolve(s))
+            .ok_or_else(|| invalid("invalid offset or unknown bookmark or symbol"))
+    }
+
+    /// Parses a length argument.
+    fn length(s: &str) -> io::Result<u64> {
+        DumpX::parse_offset(s).ok_or_else(|| invalid("invalid length"))
+    }
+}
+
+/// Builds the error reported for a malformed command.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
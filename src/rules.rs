@@ -0,0 +1,212 @@
+//! User defined coloring rules backing `--color-rules`.
+//!
+//! Each non empty line of a rules file maps a selector to a style:
+//!
+//! ```text
+//! # selector                      style
+//! bytes 0x00 0xff               = dim
+//! bytes 0x20-0x7e               = green
+//! offsets 0x0-0x3f 0x200-0x27f  = reverse
+//! pattern 55 aa                 = bold red on_blue
+//! ```
+//!
+//! `bytes` selects byte values, `offsets` inclusive offset ranges and `pattern`
+//! every occurrence of a hex byte string. Styles combine `bold`, `dim`,
+//! `italic`, `underline`, `reverse`, the eight color names, their `bright_`
+//! variants and `on_` backgrounds. When several rules select a byte, the last
+//! one wins.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+};
+
+use crate::{DumpX, search};
+
+/// What a rule applies to.
+enum Selector {
+    /// Byte values, indexed by value.
+    Bytes(Box<[bool; 256]>),
+
+    /// Inclusive offset ranges.
+    Offsets(Vec<(u64, u64)>),
+
+    /// Occurrences of a byte string, with their sorted start offsets once located.
+    Pattern { bytes: Vec<u8>, starts: Vec<u64> },
+}
+
+/// A selector and the escape sequence styling what it selects.
+struct Rule {
+    selector: Selector,
+    style: Vec<u8>,
+}
+
+/// Ordered set of coloring rules.
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Text attributes and their SGR codes.
+    const ATTRIBUTES: [(&'static str, u8); 5] = [
+        ("bold", 1),
+        ("dim", 2),
+        ("italic", 3),
+        ("underline", 4),
+        ("reverse", 7),
+    ];
+
+    /// Color names in SGR order.
+    const COLORS: [&'static str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+
+    /// Loads the rules file at `path`.
+    ///
+    /// Pattern rules select nothing until `locate` is called.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let rule = Self::parse_rule(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), i + 1, e),
+                )
+            })?;
+            rules.push(rule);
+        }
+
+        Ok(Rules { rules })
+    }
+
+    /// Parses a single `SELECTOR = STYLE` line.
+    fn parse_rule(line: &str) -> Result<Rule, &'static str> {
+        let (selector, style) = line.split_once('=').ok_or("expected SELECTOR = STYLE")?;
+        let (kind, args) = selector
+            .trim()
+            .split_once(' ')
+            .unwrap_or((selector.trim(), ""));
+
+        let selector = match kind {
+            "bytes" | "byte" => {
+                let mut set = Box::new([false; 256]);
+
+                for (start, end) in Self::parse_ranges(args)? {
+                    if end > 0xFF {
+                        return Err("byte values must be at most 0xff");
+                    }
+                    set[start as usize..=end as usize].fill(true);
+                }
+                Selector::Bytes(set)
+            }
+            "offsets" | "offset" => Selector::Offsets(Self::parse_ranges(args)?),
+            "pattern" => Selector::Pattern {
+                bytes: DumpX::parse_hex(args)
+                    .filter(|b| !b.is_empty())
+                    .ok_or("pattern requires hex bytes")?,
+                starts: Vec::new(),
+            },
+            _ => return Err("selector must be bytes, offsets or pattern"),
+        };
+
+        // Build the SGR sequence, e.g. `\x1b[1;31;44m`
+        let mut codes = Vec::new();
+        for word in style.split_whitespace() {
+            let (base, word) = match word.strip_prefix("on_") {
+                Some(color) => (40, color),
+                None => (30, word),
+            };
+            let (base, word) = match word.strip_prefix("bright_") {
+                Some(color) => (base + 60, color),
+                None => (base, word),
+            };
+
+            let code = match Self::COLORS.iter().position(|&c| c == word) {
+                Some(i) => base + i as u8,
+                None if base == 30 => Self::ATTRIBUTES
+                    .iter()
+                    .find(|&&(name, _)| name == word)
+                    .map(|&(_, code)| code)
+                    .ok_or("unknown style")?,
+                None => return Err("unknown color"),
+            };
+            codes.push(code.to_string());
+        }
+
+        if codes.is_empty() {
+            return Err("missing style");
+        }
+
+        Ok(Rule {
+            selector,
+            style: format!("\x1b[{}m", codes.join(";")).into_bytes(),
+        })
+    }
+
+    /// Parses a list of values and inclusive `START-END` ranges.
+    fn parse_ranges(s: &str) -> Result<Vec<(u64, u64)>, &'static str> {
+        let ranges = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|r| !r.is_empty())
+            .map(|r| {
+                let (start, end) = r.split_once('-').unwrap_or((r, r));
+                let (start, end) = (DumpX::parse_offset(start)?, DumpX::parse_offset(end)?);
+
+                (start <= end).then_some((start, end))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("invalid value or range")?;
+
+        if ranges.is_empty() {
+            return Err("missing values");
+        }
+
+        Ok(ranges)
+    }
+
+    /// Finds every occurrence of the pattern rules within the file at `path`.
+    pub fn locate(&mut self, path: &Path) -> io::Result<()> {
+        for rule in &mut self.rules {
+            if let Selector::Pattern {
+                ref bytes,
+                ref mut starts,
+            } = rule.selector
+            {
+                *starts = search::find(BufReader::new(File::open(path)?), bytes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the style of the last rule selecting byte `b` at `offset`, if any.
+    pub fn style(&self, offset: u64, b: u8) -> Option<&[u8]> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| match rule.selector {
+                Selector::Bytes(ref set) => set[b as usize],
+                Selector::Offsets(ref ranges) => ranges
+                    .iter()
+                    .any(|&(start, end)| (start..=end).contains(&offset)),
+                Selector::Pattern {
+                    ref bytes,
+                    ref starts,
+                } => {
+                    // All occurrences have the same length, so the last one
+                    // starting at or before `offset` is the only candidate
+                    let i = starts.partition_point(|&s| s <= offset);
+                    i > 0 && offset - starts[i - 1] < bytes.len() as u64
+                }
+            })
+            .map(|rule| rule.style.as_slice())
+    }
+}
@@ -0,0 +1,44 @@
+//! Streaming byte pattern search shared by the search commands.
+
+use std::io::{self, Read};
+
+/// I/O buffer size for reading chunks from the input.
+const IO_BUF_SIZE: usize = 64 * 1024;
+
+/// Returns the offsets of every occurrence of `needle` in `input`, overlaps included.
+///
+/// Offsets are relative to the position `input` starts reading from.
+pub fn find<R: Read>(mut input: R, needle: &[u8]) -> io::Result<Vec<u64>> {
+    let mut matches = Vec::new();
+    if needle.is_empty() {
+        return Ok(matches);
+    }
+
+    let mut io_buf = vec![0u8; IO_BUF_SIZE];
+    let mut buf = Vec::with_capacity(IO_BUF_SIZE + needle.len());
+
+    // Offset of `buf[0]` within the input
+    let mut base = 0u64;
+
+    loop {
+        let n = input.read(&mut io_buf)?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&io_buf[..n]);
+
+        for (i, window) in buf.windows(needle.len()).enumerate() {
+            if window == needle {
+                matches.push(base + i as u64);
+            }
+        }
+
+        // Keep the tail a match could still start in
+        let keep = buf.len().min(needle.len() - 1);
+        let consumed = buf.len() - keep;
+        buf.drain(..consumed);
+        base += consumed as u64;
+    }
+
+    Ok(matches)
+}
@@ -0,0 +1,207 @@
+//! Per-file session state backing `--interactive`.
+//!
+//! Bookmarks, labels, the last offset and the view options are kept in a text
+//! file under the XDG state directory, named by a hash of the input's absolute
+//! path, so reopening the same file picks up where the last session left off.
+//! The path is hashed rather than the contents, so that opening a large image
+//! stays instant and edits made with `w` keep the session. When no state
+//! directory can be found, the file lives next to the input instead.
+//!
+//! Sessions that changed nothing are not written, leaving no file behind.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::DumpX;
+
+/// State restored when the same file is opened again.
+pub struct Session {
+    /// Sidecar file the state is loaded from and saved to.
+    pub path: PathBuf,
+
+    /// Offset the last session stopped at.
+    pub offset: u64,
+
+    /// Number of bytes dumped when no length is given.
+    pub length: u64,
+
+    /// Baseline byte chosen for the view, if any.
+    pub baseline: Option<u8>,
+
+    /// Named offsets usable wherever an offset is expected.
+    pub bookmarks: BTreeMap<String, u64>,
+
+    /// Notes attached to offsets, shown in dumps.
+    pub labels: BTreeMap<u64, String>,
+
+    /// Input the session belongs to, recorded for whoever browses the state directory.
+    input: PathBuf,
+
+    /// State as last loaded or saved, so unchanged sessions are not written.
+    saved: Vec<u8>,
+}
+
+impl Session {
+    /// Suffix appended to the input's name to form the sidecar's.
+    const SUFFIX: &'static str = ".dumpx-session";
+
+    /// Directory below the state directory holding the session files.
+    const DIR: &'static str = "dumpx/sessions";
+
+    /// First line of every session file, identifying the format.
+    const MAGIC: &'static str = "# dumpx session";
+
+    /// Loads the session of `input`, or starts a fresh one with `length`.
+    ///
+    /// Returns whether an existing session was restored alongside it.
+    pub fn load(input: &Path, length: u64) -> io::Result<(Self, bool)> {
+        let input = fs::canonicalize(input)?;
+
+        let mut session = Session {
+            path: Self::path_for(&input),
+            offset: 0,
+            length,
+            baseline: None,
+            bookmarks: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            input,
+            saved: Vec::new(),
+        };
+
+        let file = match File::open(&session.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                session.saved = session.render()?;
+                return Ok((session, false));
+            }
+            Err(e) => return Err(e),
+        };
+
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: invalid session entry", session.path.display(), line),
+            )
+        };
+
+        let mut lines = BufReader::new(file).lines();
+
+        if lines.next().transpose()?.as_deref() != Some(Self::MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' is not a dumpx session", session.path.display()),
+            ));
+        }
+
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            let parsed = match key {
+                "offset" => DumpX::parse_offset(value).map(|o| session.offset = o),
+                "length" => DumpX::parse_offset(value).map(|l| session.length = l),
+                "baseline" => DumpX::parse_byte(value).map(|b| session.baseline = Some(b)),
+                "bookmark" => value.split_once(' ').and_then(|(name, offset)| {
+                    let offset = DumpX::parse_offset(offset)?;
+                    session.bookmarks.insert(name.to_owned(), offset);
+                    Some(())
+                }),
+                "label" => value.split_once(' ').and_then(|(offset, text)| {
+                    let offset = DumpX::parse_offset(offset)?;
+                    session.labels.insert(offset, text.to_owned());
+                    Some(())
+                }),
+                _ => None,
+            };
+
+            // Line numbers are 1 based and the magic line was already consumed
+            parsed.ok_or_else(|| invalid(i + 2))?;
+        }
+
+        session.saved = session.render()?;
+
+        Ok((session, true))
+    }
+
+    /// Returns the session file of the absolute path `input`.
+    fn path_for(input: &Path) -> PathBuf {
+        let state = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| {
+                env::var_os("HOME")
+                    .map(|home| Path::new(&home).join(".local/state"))
+                    .filter(|dir| dir.is_absolute())
+            });
+
+        match state {
+            Some(state) => state.join(Self::DIR).join(format!(
+                "{:016x}",
+                fnv1a(input.as_os_str().as_encoded_bytes())
+            )),
+            None => DumpX::sidecar(input, Self::SUFFIX),
+        }
+    }
+
+    /// Formats the session as written to its file.
+    fn render(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+
+        writeln!(out, "{}", Self::MAGIC)?;
+        writeln!(out, "# {}", self.input.display())?;
+        writeln!(out, "offset 0x{:016x}", self.offset)?;
+        writeln!(out, "length {}", self.length)?;
+        if let Some(b) = self.baseline {
+            writeln!(out, "baseline 0x{:02x}", b)?;
+        }
+        for (name, offset) in &self.bookmarks {
+            writeln!(out, "bookmark {} 0x{:016x}", name, offset)?;
+        }
+        for (offset, text) in &self.labels {
+            writeln!(out, "label 0x{:016x} {}", offset, text)?;
+        }
+
+        Ok(out)
+    }
+
+    /// Writes the session to its file, replacing the previous state.
+    ///
+    /// Does nothing when the state is unchanged since it was loaded or last saved.
+    pub fn save(&mut self) -> io::Result<()> {
+        let text = self.render()?;
+        if text == self.saved {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write a sibling first so a failed save never loses the old state
+        let tmp = DumpX::sidecar(&self.path, ".tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+
+        out.write_all(&text)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        fs::rename(tmp, &self.path)?;
+        self.saved = text;
+
+        Ok(())
+    }
+}
+
+/// Hashes `bytes` with 64 bit FNV-1a, which is stable across Rust releases unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
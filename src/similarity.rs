@@ -0,0 +1,210 @@
+//! Block matching similarity backing `--similarity`.
+//!
+//! The reference is cut into fixed size blocks indexed by a weak rolling hash,
+//! as in rsync. The input is then scanned byte by byte, and every window whose
+//! hash and bytes match a reference block starts a shared region, which is
+//! grown in both directions as long as the bytes keep matching.
+
+use std::collections::HashMap;
+
+/// Size of the reference blocks, the shortest run detected as shared.
+const BLOCK: usize = 64;
+
+/// A run of input bytes, either found in the reference or unique to the input.
+pub struct Region {
+    /// Offset of the run within the input.
+    pub start: usize,
+
+    /// Length of the run in bytes.
+    pub len: usize,
+
+    /// Offset of the same bytes within the reference, `None` if unique.
+    pub source: Option<usize>,
+}
+
+/// Weak rolling checksum over a `BLOCK` byte window.
+struct Rolling {
+    a: u32,
+    b: u32,
+}
+
+impl Rolling {
+    /// Computes the checksum of `window` from scratch.
+    fn new(window: &[u8]) -> Self {
+        let mut r = Rolling { a: 0, b: 0 };
+
+        for (k, &x) in window.iter().enumerate() {
+            r.a = r.a.wrapping_add(x as u32);
+            r.b = r.b.wrapping_add((window.len() - k) as u32 * x as u32);
+        }
+        r
+    }
+
+    /// Slides the window by one byte, dropping `out` and taking in `x`.
+    fn roll(&mut self, out: u8, x: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(x as u32);
+        self.b = self
+            .b
+            .wrapping_sub(BLOCK as u32 * out as u32)
+            .wrapping_add(self.a);
+    }
+
+    /// Combined 32 bit digest.
+    fn digest(&self) -> u32 {
+        (self.a & 0xFFFF) | (self.b << 16)
+    }
+}
+
+/// Splits `input` into regions shared with `reference` and regions unique to it.
+///
+/// The returned regions are in order and cover the whole input.
+pub fn compare(reference: &[u8], input: &[u8]) -> Vec<Region> {
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in reference.chunks_exact(BLOCK).enumerate() {
+        index
+            .entry(Rolling::new(block).digest())
+            .or_default()
+            .push(i * BLOCK);
+    }
+
+    let mut regions = Vec::new();
+
+    // End of the last shared region, unique bytes since then are pending
+    let mut done = 0;
+    let mut i = 0;
+    let mut rolling = None;
+
+    while i + BLOCK <= input.len() {
+        let window = &input[i..i + BLOCK];
+        let r = rolling.get_or_insert_with(|| Rolling::new(window));
+
+        let found = index.get(&r.digest()).and_then(|offsets| {
+            offsets
+                .iter()
+                .copied()
+                .find(|&o| &reference[o..o + BLOCK] == window)
+        });
+
+        let Some(source) = found else {
+            if let Some(&x) = input.get(i + BLOCK) {
+                r.roll(input[i], x);
+            }
+            i += 1;
+            continue;
+        };
+
+        // Grow the match backwards into the pending bytes, then forwards
+        let back = (1..=(i - done).min(source))
+            .take_while(|&k| input[i - k] == reference[source - k])
+            .last()
+            .unwrap_or(0);
+        let forward = (BLOCK..)
+            .take_while(|&k| {
+                i + k < input.len()
+                    && source + k < reference.len()
+                    && input[i + k] == reference[source + k]
+            })
+            .last()
+            .map_or(BLOCK, |k| k + 1);
+
+        let (start, source) = (i - back, source - back);
+        if start > done {
+            regions.push(Region {
+                start: done,
+                len: start - done,
+                source: None,
+            });
+        }
+
+        // Merge with the previous region when both sides simply continue
+        match regions.last_mut() {
+            Some(prev)
+                if prev.source.is_some_and(|s| s + prev.len == source)
+                    && prev.start + prev.len == start =>
+            {
+                prev.len += back + forward;
+            }
+            _ => regions.push(Region {
+                start,
+                len: back + forward,
+                source: Some(source),
+            }),
+        }
+
+        i += forward;
+        done = i;
+        rolling = None;
+    }
+
+    if done < input.len() {
+        regions.push(Region {
+            start: done,
+            len: input.len() - done,
+            source: None,
+        });
+    }
+
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BLOCK, compare};
+
+    /// Deterministic pseudo random bytes from a xorshift generator seeded with `seed`.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    /// Compares `input` with `reference` as `(start, len, source)` triples.
+    fn regions(reference: &[u8], input: &[u8]) -> Vec<(usize, usize, Option<usize>)> {
+        compare(reference, input)
+            .iter()
+            .map(|r| (r.start, r.len, r.source))
+            .collect()
+    }
+
+    #[test]
+    fn identical_inputs_share_one_region() {
+        // Not a multiple of the block size, the tail is found by growing the match
+        let data = noise(1, 1000);
+
+        assert_eq!(regions(&data, &data), [(0, 1000, Some(0))]);
+    }
+
+    #[test]
+    fn inserted_run_is_unique() {
+        let reference = noise(1, 1024);
+
+        let mut input = reference[..500].to_vec();
+        input.extend(noise(2, 100));
+        input.extend(&reference[500..]);
+
+        assert_eq!(
+            regions(&reference, &input),
+            [(0, 500, Some(0)), (500, 100, None), (600, 524, Some(500))]
+        );
+    }
+
+    #[test]
+    fn short_inputs_are_unique() {
+        let data = noise(1, 256);
+
+        // Nothing shorter than a block is matched, even against itself
+        assert_eq!(regions(&data, &data[..BLOCK - 1]), [(0, BLOCK - 1, None)]);
+        assert_eq!(
+            regions(&data[..BLOCK - 1], &data[..BLOCK - 1]),
+            [(0, BLOCK - 1, None)]
+        );
+        assert!(regions(&data, &[]).is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+//! Symbol map parsing backing `--map`.
+//!
+//! Three line formats are understood, so the map can be hand written or taken
+//! straight from the toolchain:
+//!
+//! ```text
+//! reset_vector=0x1f0           simple   NAME=OFFSET
+//! 0x00000000000001f0  main     GNU ld   OFFSET NAME
+//! 000001f0 T main              nm       HEX TYPE NAME
+//! ```
+//!
+//! Any other line, such as section headers and comments in linker maps, is
+//! ignored. Addresses are returned as written, the caller turns them into file
+//! offsets by subtracting the load address given with `--map-base`.
+
+use std::{fs, io, path::Path};
+
+use crate::DumpX;
+
+/// Reads the symbols of the map at `path` as `(name, address)` pairs, in file order.
+pub fn load(path: &Path) -> io::Result<Vec<(String, u64)>> {
+    let text = fs::read_to_string(path)?;
+
+    let symbols: Vec<_> = text.lines().filter_map(parse_line).collect();
+
+    if symbols.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no symbols found in map '{}'", path.display()),
+        ));
+    }
+
+    Ok(symbols)
+}
+
+/// Parses a single map line, returning `None` for lines that define no symbol.
+fn parse_line(line: &str) -> Option<(String, u64)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+
+    // Simple `NAME=OFFSET`
+    if let Some((name, offset)) = line.split_once('=') {
+        let name = name.trim();
+
+        return is_symbol(name)
+            .then(|| DumpX::parse_offset(offset.trim()))
+            .flatten()
+            .map(|offset| (name.to_owned(), offset));
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields[..] {
+        // GNU ld `0xOFFSET NAME`
+        [offset, name] if offset.starts_with("0x") && is_symbol(name) => {
+            Some((name.to_owned(), DumpX::parse_offset(offset)?))
+        }
+
+        // nm `HEX TYPE NAME`
+        [offset, kind, name] if kind.len() == 1 && is_symbol(name) => {
+            Some((name.to_owned(), u64::from_str_radix(offset, 16).ok()?))
+        }
+
+        _ => None,
+    }
+}
+
+/// Returns whether `name` looks like a symbol rather than a section or expression.
+fn is_symbol(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '.' | '@'))
+}